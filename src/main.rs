@@ -1,29 +1,153 @@
-use clap::{crate_authors, crate_version, Arg, ArgAction, Command};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+mod metadata;
+
+use clap::{crate_authors, crate_version, value_parser, Arg, ArgAction, ArgMatches, Command};
+use futures::stream::{self, StreamExt};
 use log::{error, info, warn};
 use lopdf::{Dictionary, Document, Object};
 use regex::Regex;
-use std::collections::HashSet;
-use waybackmachine_client::{ArchiveResult, ClientConfig, Error, WaybackMachineClient};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use url::Url;
+use waybackmachine_client::{
+    ArchiveOptions, ArchiveOutcome, ArchiveReport, ArchiveResult, Capture, CaptureFailure,
+    ClientConfig, DenyList, Error, ExclusionList, Freshness, FreshnessRules, LinkCheck, LocalWarc,
+    PermaCc, Policies, Quorum, RedirectStrategy, RetrySettings, Soft404Detection, SystemResolver,
+    WaybackMachine, WaybackMachineClient,
+};
+
+// Arguments selecting the URLs of the PDF and its publication date, shared by
+// the archive command and the check subcommand
+fn input_args() -> Vec<Arg> {
+    vec![
+        Arg::new("file")
+            .value_name("FILE")
+            .help("Sets the input PDF file to use")
+            .required(true),
+        Arg::new("exclude")
+            .long("exclude")
+            .value_name("PATTERN")
+            .help("Excludes URLs matching the pattern")
+            .required(false)
+            .action(ArgAction::Append),
+        Arg::new("exclusions")
+            .long("exclusions")
+            .value_name("FILE")
+            .help(
+                "Reads domains to exclude, or to stop excluding if prefixed with '-', from a file",
+            )
+            .required(false),
+        Arg::new("exclude-domain")
+            .long("exclude-domain")
+            .value_name("DOMAIN")
+            .help("Excludes URLs in the domain and its subdomains")
+            .required(false)
+            .action(ArgAction::Append),
+        Arg::new("include-domain")
+            .long("include-domain")
+            .value_name("DOMAIN")
+            .help("Archives URLs in the domain even if excluded by default")
+            .required(false)
+            .action(ArgAction::Append),
+        Arg::new("published")
+            .long("published")
+            .value_name("YYYY-MM-DD")
+            .help("Publication date snapshots are looked up near, instead of the PDF's date")
+            .value_parser(parse_published),
+        Arg::new("pdf-date")
+            .long("pdf-date")
+            .value_name("DATE")
+            .help("PDF metadata date used as the publication date, unless --published is given")
+            .value_parser(["creation", "modification"])
+            .default_value("creation"),
+        Arg::new("jobs")
+            .short('j')
+            .long("jobs")
+            .value_name("N")
+            .help("Number of URLs to process in parallel")
+            .value_parser(value_parser!(u64).range(1..))
+            .default_value("1"),
+    ]
+}
 
 fn cli() -> Command {
     Command::new(env!("CARGO_PKG_NAME"))
         .version(crate_version!())
         .author(crate_authors!("\n"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("check")
+                .about("Checks the links of a PDF for link rot without archiving them, reporting their usable snapshots")
+                .args(input_args()),
+        )
+        .args(input_args())
         .arg(
-            Arg::new("file")
+            Arg::new("freshness-rules")
+                .long("freshness-rules")
                 .value_name("FILE")
-                .help("Sets the input PDF file to use")
-                .required(true),
+                .help("Reads per-domain archive freshness rules from a TOML file")
+                .required(false),
         )
         .arg(
-            Arg::new("exclude")
-                .long("exclude")
-                .value_name("PATTERN")
-                .help("Excludes URLs matching the pattern")
-                .required(false)
+            Arg::new("redirects")
+                .long("redirects")
+                .value_name("STRATEGY")
+                .help("Archives the original URL, the final location of redirected URLs, or both")
+                .value_parser(["original", "final", "both"])
+                .default_value("original"),
+        )
+        .arg(
+            Arg::new("archive")
+                .long("archive")
+                .value_name("ARCHIVE")
+                .help("Archive to capture URLs in, repeated to use several. Perma.cc requires an API key in PERMA_API_KEY; local writes WARC files to --warc-dir")
+                .value_parser(["wayback", "perma.cc", "local"])
+                .default_value("wayback")
                 .action(ArgAction::Append),
         )
+        .arg(
+            Arg::new("warc-dir")
+                .long("warc-dir")
+                .value_name("DIR")
+                .help("Directory of the WARC file and CDXJ index written by the local archive")
+                .default_value("warc"),
+        )
+        .arg(
+            Arg::new("quorum")
+                .long("quorum")
+                .value_name("N")
+                .help("Number of archives that must preserve each URL, or 'all'")
+                .value_parser(parse_quorum)
+                .default_value("all"),
+        )
+        .arg(
+            Arg::new("dead-links")
+                .long("dead-links")
+                .help("Reports the snapshot closest to the publication date of dead URLs instead of archiving them")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("soft-404")
+                .long("soft-404")
+                .value_name("ACTION")
+                .help("Looks for pages answering successfully that are likely missing (soft 404s), to report them or skip archiving them")
+                .value_parser(["off", "report", "skip"])
+                .default_value("off"),
+        )
+        .arg(
+            Arg::new("verify")
+                .long("verify")
+                .help("Compares each new capture with the live page, flagging error pages, CAPTCHAs and cookie walls")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("publication-window")
+                .long("publication-window")
+                .value_name("DURATION")
+                .help("Skips URLs with a snapshot within this long of the publication date (e.g. 6mo), instead of one from the last 30 days")
+                .value_parser(parse_window),
+        )
 }
 
 #[tokio::main]
@@ -33,7 +157,215 @@ async fn main() {
         .init();
 
     let args = cli().get_matches();
+    if let Some(check_args) = args.subcommand_matches("check") {
+        std::process::exit(check_links(check_args).await);
+    }
+    let (links_set, reference_date, policy) = read_input(&args);
+    let redirect_strategy = match args.get_one::<String>("redirects").unwrap().as_str() {
+        "final" => RedirectStrategy::Final,
+        "both" => RedirectStrategy::Both,
+        _ => RedirectStrategy::Original,
+    };
+    let mut client_config = ClientConfig::builder()
+        .policy(policy)
+        .redirect_strategy(redirect_strategy)
+        .closest_for_dead_links(args.get_flag("dead-links"))
+        .soft_404_detection(match args.get_one::<String>("soft-404").unwrap().as_str() {
+            "report" => Soft404Detection::Report,
+            "skip" => Soft404Detection::Skip,
+            _ => Soft404Detection::Off,
+        })
+        .verify_captures(args.get_flag("verify"))
+        .resolver(SystemResolver);
+    if let Some(window) = args.get_one::<TimeDelta>("publication-window") {
+        client_config = client_config.reference_window(*window);
+    }
+    // archives are deduplicated but kept in the order given, the first one being
+    // used for lookups
+    let mut archives: Vec<&String> = Vec::new();
+    for archive in args.get_many::<String>("archive").unwrap_or_default() {
+        if !archives.contains(&archive) {
+            archives.push(archive);
+        }
+    }
+    for archive in archives {
+        client_config = match archive.as_str() {
+            "perma.cc" => match std::env::var("PERMA_API_KEY") {
+                Ok(api_key) => client_config.backend(PermaCc::new(api_key)),
+                Err(_) => {
+                    error!("PERMA_API_KEY must be set to archive in Perma.cc");
+                    std::process::exit(1);
+                }
+            },
+            "local" => {
+                client_config.backend(LocalWarc::new(args.get_one::<String>("warc-dir").unwrap()))
+            }
+            _ => client_config.backend(WaybackMachine::default()),
+        };
+    }
+    client_config = client_config.quorum(*args.get_one::<Quorum>("quorum").unwrap());
+    if let Some(rules_file) = args.get_one::<String>("freshness-rules") {
+        match FreshnessRules::from_file(rules_file) {
+            Ok(rules) => client_config = client_config.freshness_rules(rules),
+            Err(err) => {
+                error!("{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+    let client = match client_config.build() {
+        Ok(client_config) => WaybackMachineClient::new(client_config),
+        Err(err) => {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let jobs = *args.get_one::<u64>("jobs").unwrap() as usize;
+    let options = ArchiveOptions {
+        reference_date,
+        ..Default::default()
+    };
+
+    // Links are grouped by host, and the links of a host archived one after
+    // another: once its owner is found to exclude it from the Wayback Machine,
+    // its remaining links are skipped instead of being refused one by one,
+    // whatever the number of jobs
+    let mut hosts: Vec<Vec<(usize, String)>> = Vec::new();
+    let mut host_groups: HashMap<Option<String>, usize> = HashMap::new();
+    for (index, url) in links_set.into_iter().enumerate() {
+        let host = Url::parse(&url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        let group = *host_groups.entry(host).or_insert_with(|| {
+            hosts.push(Vec::new());
+            hosts.len() - 1
+        });
+        hosts[group].push((index, url));
+    }
+    let mut results = stream::iter(hosts)
+        .map(|links| {
+            let client = &client;
+            let options = &options;
+            Box::pin(stream::unfold(
+                (links.into_iter(), false),
+                move |(mut links, excluded)| async move {
+                    let (index, url) = links.next()?;
+                    if excluded {
+                        return Some(((index, url, None), (links, true)));
+                    }
+                    let result = client.archive_url_with_options(&url, options).await;
+                    let excluded = result.as_ref().is_err_and(excluded_by_site_owner);
+                    Some(((index, url, Some(result)), (links, excluded)))
+                },
+            ))
+        })
+        .flatten_unordered(jobs);
+
+    // Results are logged in the order URLs were submitted, regardless of which
+    // request completes first, so that the log output is stable between runs
+    let mut pending = BTreeMap::new();
+    let mut next_index = 0;
+    let mut exit_code = 0;
+    while let Some((index, url, result)) = results.next().await {
+        pending.insert(index, (url, result));
+        while let Some((url, result)) = pending.remove(&next_index) {
+            next_index += 1;
+            exit_code = exit_code.max(log_result(url, result));
+        }
+    }
+    // captures only append to the index of the local archive, sorted once done
+    if args
+        .get_many::<String>("archive")
+        .unwrap_or_default()
+        .any(|archive| archive == "local")
+    {
+        let local_warc = LocalWarc::new(args.get_one::<String>("warc-dir").unwrap());
+        if let Err(err) = local_warc.sort_index() {
+            error!("{}", err);
+            exit_code = 1;
+        }
+    }
+    std::process::exit(exit_code);
+}
+
+// Log the result of archiving a URL, returning the exit code it calls for:
+// failure (1) if the URL was not preserved and may be later
+fn log_result(url: String, result: Option<Result<ArchiveReport, Error>>) -> i32 {
+    let Some(result) = result else {
+        info!("Skipped (host excluded by site owner): {}", url);
+        return 0;
+    };
+    match result {
+        Ok(report) => {
+            log_report(report);
+            0
+        }
+        Err(Error::ExcludedUrl(url, reason)) => {
+            info!("Skipped ({}): {}", reason, url);
+            0
+        }
+        Err(Error::LikelySoft404 { url, soft_404 }) => {
+            warn!("Skipped (likely soft 404, {}): {}", soft_404, url);
+            0
+        }
+        Err(Error::InvalidUrl(url, reason)) => {
+            warn!("Skipped ({}): {}", reason, url);
+            0
+        }
+        // retrying cannot help, so these do not fail the run
+        Err(Error::CannotArchive { url, cause, .. }) if cause.is_permanent() => {
+            warn!("Skipped ({}): {}", cause, url);
+            0
+        }
+        Err(e)
+            if e.capture_failure()
+                .is_some_and(CaptureFailure::is_retryable) =>
+        {
+            error!("{} (retry later)", error_chain(&e));
+            1
+        }
+        Err(Error::QuorumNotReached {
+            url,
+            required,
+            report,
+        }) => {
+            log_report(*report);
+            error!("Not preserved in {} archives: {}", required, url);
+            1
+        }
+        Err(e) => {
+            error!("{}", error_chain(&e));
+            1
+        }
+    }
+}
+
+// Whether the site owner excluded the URL's host from the Wayback Machine,
+// including when it was refused by one of several archives
+fn excluded_by_site_owner(err: &Error) -> bool {
+    let excluded = |err: &Error| {
+        matches!(
+            err,
+            Error::CannotArchive {
+                cause: CaptureFailure::ExcludedBySiteOwner,
+                ..
+            }
+        )
+    };
+    match err {
+        Error::QuorumNotReached { report, .. } => {
+            report.failures().any(|(_, _, err)| excluded(err))
+        }
+        err => excluded(err),
+    }
+}
+
+// Read the links of the input PDF, its publication date, and the policy deciding
+// which links are processed
+fn read_input(args: &ArgMatches) -> (BTreeSet<String>, Option<NaiveDateTime>, Policies) {
     let pdf_file = args.get_one::<String>("file").unwrap();
+
     let doc = match Document::load(pdf_file) {
         Ok(doc) => doc,
         Err(err) => {
@@ -47,42 +379,228 @@ async fn main() {
         .map(|pattern| Regex::new(pattern).expect("Invalid regex pattern"))
         .collect();
 
+    let pdf_dates = metadata::pdf_dates(&doc);
+    let reference_date = args.get_one::<NaiveDateTime>("published").copied().or(
+        match args.get_one::<String>("pdf-date").unwrap().as_str() {
+            "modification" => pdf_dates.modification,
+            _ => pdf_dates.creation,
+        },
+    );
     let links_set = extract_links(doc);
     if links_set.is_empty() {
         warn!("No page annotations found in this PDF file");
     }
-    let client = WaybackMachineClient::new(ClientConfig::default());
+    // patterns are matched against the links as written in the PDF, before the
+    // client normalises them; the deny list then catches the redirects they lead to
+    let links_set = links_set
+        .into_iter()
+        .filter(
+            |link| match regex_patterns.iter().find(|regex| regex.is_match(link)) {
+                Some(regex) => {
+                    info!("Skipped (matches pattern {}): {}", regex, link);
+                    false
+                }
+                None => true,
+            },
+        )
+        .collect();
+    let mut exclusions = ExclusionList::default();
+    if let Some(exclusions_file) = args.get_one::<String>("exclusions") {
+        if let Err(err) = exclusions.extend_from_file(exclusions_file) {
+            error!("{}", err);
+            std::process::exit(1);
+        }
+    }
+    for domain in args
+        .get_many::<String>("exclude-domain")
+        .unwrap_or_default()
+    {
+        exclusions.add(domain);
+    }
+    for domain in args
+        .get_many::<String>("include-domain")
+        .unwrap_or_default()
+    {
+        exclusions.remove(domain);
+    }
 
-    let mut exit_code = 0;
-    for url in links_set.into_iter() {
-        if regex_patterns.iter().any(|regex| regex.is_match(&url)) {
-            info!("Skipped: {}", url);
-            continue;
+    let policy = Policies::new()
+        .with(exclusions)
+        .with(DenyList::new(regex_patterns));
+    (links_set, reference_date, policy)
+}
+
+// Check every link of the PDF without archiving it, returning the exit code:
+// failure (1) if any link is broken
+async fn check_links(args: &ArgMatches) -> i32 {
+    let (links_set, reference_date, policy) = read_input(args);
+    // a link that does not answer is reported as unreachable rather than retried
+    let client = match ClientConfig::builder()
+        .policy(policy)
+        .resolver(SystemResolver)
+        .target_retry(RetrySettings::none())
+        .build()
+    {
+        Ok(client_config) => WaybackMachineClient::new(client_config),
+        Err(err) => {
+            error!("{}", err);
+            return 1;
         }
+    };
+    let jobs = *args.get_one::<u64>("jobs").unwrap() as usize;
+    let options = ArchiveOptions {
+        reference_date,
+        ..Default::default()
+    };
 
-        match client.archive_url(&url).await {
-            Ok(ArchiveResult::Archived(archive_url)) => {
-                info!("Archived: {} – {}", url, archive_url)
+    let mut results = stream::iter(links_set)
+        .map(|url| {
+            let client = &client;
+            let options = &options;
+            async move { (url.clone(), client.check_url(&url, options).await) }
+        })
+        .buffered(jobs);
+
+    let (mut checked, mut broken, mut failed, mut without_snapshot) = (0, 0, 0, 0);
+    while let Some((url, result)) = results.next().await {
+        let LinkCheck {
+            redirect_chain,
+            status,
+            snapshot,
+        } = match result {
+            Ok(check) => check,
+            Err(Error::ExcludedUrl(url, reason)) => {
+                info!("Skipped ({}): {}", reason, url);
+                continue;
             }
-            Ok(ArchiveResult::RecentArchiveExists) => {
-                info!("Skipped: {}", url)
+            Err(e) => {
+                checked += 1;
+                failed += 1;
+                error!("Check failed ({}): {}", error_chain(&e), url);
+                continue;
             }
-            Err(Error::ExcludedUrl(url)) => {
-                info!("Skipped: {}", url)
+        };
+        checked += 1;
+        let snapshot = match snapshot {
+            Ok(Some(snapshot)) => snapshot.to_string(),
+            Ok(None) => {
+                without_snapshot += 1;
+                "no snapshot".to_string()
             }
-            Err(e) => {
-                error!("{}", e);
-                // Set exit code to failure (1) if any URL fails to archive
-                exit_code = 1;
+            Err(e) => format!("snapshot lookup failed: {}", error_chain(&e)),
+        };
+        let location = if redirect_chain.is_redirected() {
+            redirect_chain.to_string()
+        } else {
+            url
+        };
+        if status.is_broken() {
+            broken += 1;
+            warn!("Broken ({}): {} – {}", status, location, snapshot);
+        } else {
+            info!("{}: {} – {}", status, location, snapshot);
+        }
+    }
+    info!(
+        "Checked {} links: {} broken, {} failed, {} without snapshot",
+        checked, broken, failed, without_snapshot
+    );
+    i32::from(broken > 0 || failed > 0)
+}
+
+// Log the outcome of archiving a URL in each archive
+fn log_report(
+    ArchiveReport {
+        redirect_chain,
+        captures,
+        soft_404,
+    }: ArchiveReport,
+) {
+    if redirect_chain.is_redirected() {
+        info!("Redirected: {}", redirect_chain)
+    }
+    if let Some(soft_404) = soft_404 {
+        warn!(
+            "Likely soft 404 ({}): {}",
+            soft_404,
+            redirect_chain.final_url()
+        )
+    }
+    for Capture { url, outcomes } in captures {
+        let several = outcomes.len() > 1;
+        for ArchiveOutcome {
+            archive,
+            result,
+            verification,
+        } in outcomes
+        {
+            // the archive is only named when several are used
+            let label = if several {
+                format!(" in {}", archive)
+            } else {
+                String::new()
+            };
+            match result {
+                Ok(ArchiveResult::Archived(archive_url)) => match verification {
+                    Some(verification) if !verification.is_verified() => warn!(
+                        "Archived{} ({}): {} – {}",
+                        label, verification, url, archive_url
+                    ),
+                    _ => info!("Archived{}: {} – {}", label, url, archive_url),
+                },
+                Ok(ArchiveResult::RecentArchiveExists) => {
+                    info!("Skipped{}: {}", label, url)
+                }
+                Ok(ArchiveResult::ClosestSnapshot(snapshot)) => {
+                    info!("Dead{}: {} – {}", label, url, snapshot)
+                }
+                Err(e) => warn!("Failed{}: {}", label, error_chain(&e)),
             }
         }
     }
-    std::process::exit(exit_code);
+}
+
+// Parse a quorum given as a number of archives, or "all"
+fn parse_quorum(value: &str) -> Result<Quorum, String> {
+    match value {
+        "all" => Ok(Quorum::All),
+        _ => match value.parse::<usize>() {
+            Ok(required) if required > 0 => Ok(Quorum::AtLeast(required)),
+            _ => Err("expected a positive number or 'all'".into()),
+        },
+    }
+}
+
+// Parse a duration given as a freshness threshold, such as 6mo or 1y
+fn parse_window(value: &str) -> Result<TimeDelta, String> {
+    match value.parse::<Freshness>() {
+        Ok(Freshness::MaxAge(window)) => Ok(window),
+        _ => Err("expected a number followed by s, m, h, d, w, mo or y".into()),
+    }
+}
+
+// Parse a publication date given as YYYY-MM-DD
+fn parse_published(value: &str) -> Result<NaiveDateTime, String> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_time(NaiveTime::MIN))
+        .map_err(|e| e.to_string())
+}
+
+// Format an error followed by its causes
+fn error_chain(err: &dyn std::error::Error) -> String {
+    let mut message = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
 }
 
 // Extract all Links from a PDF
-fn extract_links(doc: Document) -> HashSet<String> {
-    let mut links_set = HashSet::new();
+fn extract_links(doc: Document) -> BTreeSet<String> {
+    let mut links_set = BTreeSet::new();
 
     for page_id in doc.page_iter() {
         for annotation in doc.get_page_annotations(page_id) {
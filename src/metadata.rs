@@ -0,0 +1,210 @@
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta};
+use lopdf::{Document, Object};
+use regex::Regex;
+
+// Dates recorded in the metadata of a PDF, in UTC
+#[derive(Debug, Default, PartialEq)]
+pub struct PdfDates {
+    pub creation: Option<NaiveDateTime>,
+    pub modification: Option<NaiveDateTime>,
+}
+
+// Read the creation and modification dates of the PDF from its document information
+// dictionary, falling back to its XMP metadata
+pub fn pdf_dates(doc: &Document) -> PdfDates {
+    let xmp = xmp_metadata(doc).unwrap_or_default();
+    PdfDates {
+        creation: info_date(doc, b"CreationDate").or_else(|| xmp_date(&xmp, "CreateDate")),
+        modification: info_date(doc, b"ModDate").or_else(|| xmp_date(&xmp, "ModifyDate")),
+    }
+}
+
+fn info_date(doc: &Document, key: &[u8]) -> Option<NaiveDateTime> {
+    let info = doc.dereference(doc.trailer.get(b"Info").ok()?).ok()?.1;
+    match info.as_dict().ok()?.get_deref(key, doc).ok()? {
+        Object::String(date, _) => parse_pdf_date(&decode_pdf_string(date)),
+        _ => None,
+    }
+}
+
+// Read the XMP packet referenced by the document catalog
+fn xmp_metadata(doc: &Document) -> Option<String> {
+    let stream = doc
+        .catalog()
+        .ok()?
+        .get_deref(b"Metadata", doc)
+        .ok()?
+        .as_stream()
+        .ok()?;
+    // metadata streams are usually left uncompressed so that they stay readable
+    let content = stream
+        .decompressed_content()
+        .unwrap_or_else(|_| stream.content.clone());
+    Some(String::from_utf8_lossy(&content).into_owned())
+}
+
+// Find an xmp: property, written either as an element or as an attribute
+fn xmp_date(xmp: &str, property: &str) -> Option<NaiveDateTime> {
+    let pattern = format!(r#"xmp:{}(?:\s*>|\s*=\s*["'])\s*([^<"']+)"#, property);
+    let captures = Regex::new(&pattern).ok()?.captures(xmp)?;
+    parse_xmp_date(captures.get(1)?.as_str())
+}
+
+// Decode a PDF text string, either UTF-16BE with a byte order mark or PDFDocEncoding,
+// which matches ASCII for dates
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    match bytes.strip_prefix(&[0xFE, 0xFF]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+// Parse a PDF date, D:YYYYMMDDHHmmSSOHH'mm', where everything after the year
+// is optional, into UTC
+fn parse_pdf_date(date: &str) -> Option<NaiveDateTime> {
+    let date = date.trim().trim_start_matches("D:");
+    let digits = date.chars().take_while(char::is_ascii_digit).count();
+    let field = |start: usize, default: u32| -> Option<u32> {
+        if start + 2 <= digits {
+            date[start..start + 2].parse().ok()
+        } else {
+            Some(default)
+        }
+    };
+    if digits < 4 {
+        return None;
+    }
+    let local = NaiveDate::from_ymd_opt(date[..4].parse().ok()?, field(4, 1)?, field(6, 1)?)?
+        .and_hms_opt(field(8, 0)?, field(10, 0)?, field(12, 0)?)?;
+    // the offset from UT follows the digits as +HH'mm', -HH'mm' or Z
+    let offset = &date[digits..];
+    let sign = match offset.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Some(local),
+    };
+    let mut parts = offset[1..].split('\'');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = match parts.next() {
+        Some(minutes) if !minutes.is_empty() => minutes.parse().ok()?,
+        _ => 0,
+    };
+    if !(0..=23).contains(&hours) || !(0..=59).contains(&minutes) {
+        return None;
+    }
+    local.checked_sub_signed(TimeDelta::try_minutes(sign * (hours * 60 + minutes))?)
+}
+
+// Parse an XMP date, an ISO 8601 date or date and time where the time, its seconds
+// and the time zone are optional, into UTC
+fn parse_xmp_date(date: &str) -> Option<NaiveDateTime> {
+    let date = date.trim();
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+        return Some(datetime.naive_utc());
+    }
+    if let Ok(datetime) = DateTime::parse_from_str(date, "%Y-%m-%dT%H:%M%:z") {
+        return Some(datetime.naive_utc());
+    }
+    let date = date.trim_end_matches('Z');
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+        .or_else(|| {
+            // reduced precision: YYYY-MM-DD, YYYY-MM or YYYY
+            let mut parts = date.splitn(3, '-').map(str::parse::<u32>);
+            let year = parts.next()?.ok()?;
+            let month = parts.next().unwrap_or(Ok(1)).ok()?;
+            let day = parts.next().unwrap_or(Ok(1)).ok()?;
+            NaiveDate::from_ymd_opt(year as i32, month, day).map(|d| d.and_time(NaiveTime::MIN))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lopdf::{dictionary, Stream, StringFormat};
+
+    fn date(date: &str) -> Option<NaiveDateTime> {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S").ok()
+    }
+
+    #[test]
+    fn pdf_date_formats() {
+        assert_eq!(
+            parse_pdf_date("D:20190601103000+01'00'"),
+            date("2019-06-01 09:30:00")
+        );
+        assert_eq!(
+            parse_pdf_date("D:20190601103000-05'30"),
+            date("2019-06-01 16:00:00")
+        );
+        assert_eq!(
+            parse_pdf_date("D:20190601103000Z"),
+            date("2019-06-01 10:30:00")
+        );
+        assert_eq!(parse_pdf_date("D:2019"), date("2019-01-01 00:00:00"));
+        assert_eq!(parse_pdf_date("D:20191301"), None);
+        assert_eq!(parse_pdf_date("D:2019+9999999999'"), None);
+        assert_eq!(parse_pdf_date("D:20190601103000+01'75'"), None);
+        assert_eq!(parse_pdf_date("June 2019"), None);
+    }
+
+    #[test]
+    fn xmp_date_formats() {
+        assert_eq!(
+            parse_xmp_date("2019-06-01T10:30:00+01:00"),
+            date("2019-06-01 09:30:00")
+        );
+        assert_eq!(
+            parse_xmp_date("2019-06-01T10:30+01:00"),
+            date("2019-06-01 09:30:00")
+        );
+        assert_eq!(
+            parse_xmp_date("2019-06-01T10:30:00"),
+            date("2019-06-01 10:30:00")
+        );
+        assert_eq!(parse_xmp_date("2019-06"), date("2019-06-01 00:00:00"));
+        assert_eq!(parse_xmp_date("2019"), date("2019-01-01 00:00:00"));
+    }
+
+    #[test]
+    fn document_dates() {
+        let mut doc = Document::with_version("1.7");
+        let utf16: Vec<u8> = [0xFE, 0xFF]
+            .into_iter()
+            .chain("D:20190601".encode_utf16().flat_map(u16::to_be_bytes))
+            .collect();
+        let info = doc.add_object(dictionary! {
+            "ModDate" => Object::String(utf16, StringFormat::Hexadecimal),
+        });
+        let xmp = r#"<x:xmpmeta><rdf:RDF><rdf:Description xmp:ModifyDate="2020-01-01T00:00:00Z">
+            <xmp:CreateDate>2019-05-01T12:00:00Z</xmp:CreateDate>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let metadata = doc.add_object(Stream::new(
+            dictionary! { "Type" => "Metadata", "Subtype" => "XML" },
+            xmp.as_bytes().to_vec(),
+        ));
+        let catalog = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Metadata" => metadata,
+        });
+        doc.trailer.set("Root", catalog);
+        doc.trailer.set("Info", info);
+
+        assert_eq!(
+            pdf_dates(&doc),
+            PdfDates {
+                // only found in the XMP metadata
+                creation: date("2019-05-01 12:00:00"),
+                // the information dictionary takes precedence
+                modification: date("2019-06-01 00:00:00"),
+            }
+        );
+    }
+}
@@ -1,6 +1,9 @@
-use crate::Error;
+use crate::policy::{ArchivePolicy, Decision, Policies};
+use crate::{Error, InvalidReason};
 use std::fmt;
-use url::{Host, Url};
+use std::fs;
+use std::path::Path;
+use url::Url;
 
 #[derive(Clone)]
 /// Validator for archivable URLs
@@ -9,7 +12,7 @@ pub struct ArchivableUrl {
 }
 
 /// List of domains that block wayback requests
-const EXCLUDED_DOMAINS: &[&str] = &[
+pub const EXCLUDED_DOMAINS: &[&str] = &[
     "archive.org",
     "jstor.org",
     "diw.de",
@@ -32,61 +35,135 @@ const EXCLUDED_DOMAINS: &[&str] = &[
     "ucpress.edu",
 ];
 
-impl ArchivableUrl {
-    /// Parses and validates the URL for archiving
-    pub fn parse(url: &str) -> Result<Self, Error> {
-        let parsed_url = Url::parse(url).map_err(|_| Error::InvalidUrl(url.to_string()))?;
-        let archivable_url = Self { url: parsed_url };
-        archivable_url.validate_url()
+/// Domains that are not to be archived, along with all their subdomains.
+///
+/// The default list contains `EXCLUDED_DOMAINS`; domains can be added or removed
+/// at runtime, or from a file with one domain per line. In a file, a domain
+/// prefixed with `-` is removed from the list, and lines starting with `#` are ignored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ExclusionList {
+    domains: Vec<String>,
+}
+
+impl ExclusionList {
+    /// Constructs an empty exclusion list
+    pub fn empty() -> Self {
+        ExclusionList {
+            domains: Vec::new(),
+        }
     }
 
-    /// Validates the URL for archiving
-    fn validate_url(self) -> Result<Self, Error> {
-        let host = match self.url.host() {
-            Some(host) => host,
-            None => return Err(Error::InvalidUrl(self.url.to_string())),
-        };
-
-        // Check if the host is excluded
-        match host {
-            Host::Domain(domain) => {
-                if domain.contains("localhost") {
-                    return Err(Error::InvalidUrl(self.url.to_string()));
-                }
-
-                for &pattern in EXCLUDED_DOMAINS {
-                    if domain.contains(pattern) {
-                        return Err(Error::ExcludedUrl(self.url.to_string()));
-                    }
-                }
-            }
-            Host::Ipv4(ipv4)
-                if ipv4.is_loopback()
-                    || ipv4.is_private()
-                    || ipv4.is_multicast()
-                    || ipv4.is_unspecified() =>
-            {
-                return Err(Error::InvalidUrl(self.url.to_string()));
+    /// Excludes a domain and its subdomains
+    pub fn add(&mut self, domain: &str) {
+        let domain = normalise_domain(domain);
+        if !domain.is_empty() && !self.domains.contains(&domain) {
+            self.domains.push(domain);
+        }
+    }
+
+    /// Stops excluding a domain. Its parent domains, if listed, still apply.
+    pub fn remove(&mut self, domain: &str) {
+        let domain = normalise_domain(domain);
+        self.domains.retain(|excluded| *excluded != domain);
+    }
+
+    /// Returns the excluded domains
+    pub fn domains(&self) -> &[String] {
+        &self.domains
+    }
+
+    /// Returns the rule excluding the given domain, if any
+    pub fn matching_rule(&self, domain: &str) -> Option<&str> {
+        self.domains
+            .iter()
+            .find(|excluded| is_subdomain_of(domain, excluded))
+            .map(String::as_str)
+    }
+
+    /// Applies the additions and removals listed in the given input
+    pub fn extend_from_str(&mut self, input: &str) {
+        for line in input.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
             }
-            Host::Ipv6(ipv6) if ipv6.is_loopback() || ipv6.is_multicast() => {
-                return Err(Error::InvalidUrl(self.url.to_string()));
+            match line.strip_prefix('-') {
+                Some(domain) => self.remove(domain),
+                None => self.add(line),
             }
-            _ => {}
         }
+    }
 
-        // Check for non-HTTP(S) protocols
-        if !["http", "https"].contains(&self.url.scheme()) {
-            return Err(Error::InvalidUrl(self.url.to_string()));
+    /// Applies the additions and removals listed in the given file
+    pub fn extend_from_file(&mut self, path: impl AsRef<Path>) -> Result<(), Error> {
+        let path = path.as_ref();
+        let input = fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("Cannot read {}: {}", path.display(), e)))?;
+        self.extend_from_str(&input);
+        Ok(())
+    }
+}
+
+impl Default for ExclusionList {
+    /// Constructs an exclusion list with the domains known to block wayback requests
+    fn default() -> Self {
+        let mut exclusions = ExclusionList::empty();
+        for domain in EXCLUDED_DOMAINS {
+            exclusions.add(domain);
         }
+        exclusions
+    }
+}
+
+fn normalise_domain(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_ascii_lowercase()
+}
 
-        // If none of the filters matched, the URL is valid for archiving
-        Ok(self)
+impl ArchivableUrl {
+    /// Parses and validates the URL for archiving, using the default `Policies`
+    pub fn parse(url: &str) -> Result<Self, Error> {
+        Self::parse_with_policy(url, &Policies::default())
+    }
+
+    /// Parses and validates the URL for archiving, using the given policy
+    pub fn parse_with_policy(url: &str, policy: &dyn ArchivePolicy) -> Result<Self, Error> {
+        let parsed_url = Url::parse(url)
+            .map_err(|e| Error::InvalidUrl(url.to_string(), InvalidReason::Unparseable(e)))?;
+        let archivable_url = Self { url: parsed_url };
+        archivable_url.validate_url(policy)
+    }
+
+    /// Validates the URL for archiving
+    fn validate_url(self, policy: &dyn ArchivePolicy) -> Result<Self, Error> {
+        match policy.check(&self.url) {
+            Decision::Allow => Ok(self),
+            Decision::Reject(reason) => Err(Error::InvalidUrl(self.url.to_string(), reason)),
+            Decision::Exclude(reason) => Err(Error::ExcludedUrl(self.url.to_string(), reason)),
+        }
     }
 
     /// Returns the URL as a string
     pub fn as_str(&self) -> &str {
         self.url.as_str()
     }
+
+    /// Returns the URL without its fragment, which is never sent to the server
+    pub fn without_fragment(&self) -> Url {
+        let mut url = self.url.clone();
+        url.set_fragment(None);
+        url
+    }
+}
+
+/// Checks if `domain` is `parent` or one of its subdomains, comparing whole labels
+/// so that `summit.edu` is not considered a subdomain of `mit.edu`.
+pub(crate) fn is_subdomain_of(domain: &str, parent: &str) -> bool {
+    let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+    let parent = parent.trim_end_matches('.').to_ascii_lowercase();
+    match domain.strip_suffix(&parent) {
+        Some("") => true,
+        Some(prefix) => prefix.ends_with('.'),
+        None => false,
+    }
 }
 
 impl fmt::Display for ArchivableUrl {
@@ -122,7 +199,12 @@ mod tests {
         let url = "invalid-url";
         let result = ArchivableUrl::parse(url);
         assert!(result.is_err());
-        assert_eq!(result.err(), Some(Error::InvalidUrl(url.to_string())));
+        assert!(matches!(
+            result.err(),
+            Some(Error::InvalidUrl(u, r))
+                if u == url
+                    && r == InvalidReason::Unparseable(url::ParseError::RelativeUrlWithoutBase)
+        ));
     }
 
     #[test]
@@ -130,7 +212,11 @@ mod tests {
         let url = "ftp://example.com/";
         let result = ArchivableUrl::parse(url);
         assert!(result.is_err());
-        assert_eq!(result.err(), Some(Error::InvalidUrl(url.to_string())));
+        assert!(matches!(
+            result.err(),
+            Some(Error::InvalidUrl(u, r))
+                if u == url && r == InvalidReason::UnsupportedScheme("ftp".into())
+        ));
     }
 
     #[test]
@@ -138,7 +224,11 @@ mod tests {
         let url = "http://localhost/";
         let result = ArchivableUrl::parse(url);
         assert!(result.is_err());
-        assert_eq!(result.err(), Some(Error::InvalidUrl(url.to_string())));
+        assert!(matches!(
+            result.err(),
+            Some(Error::InvalidUrl(u, r))
+                if u == url && r == InvalidReason::Localhost
+        ));
     }
 
     #[test]
@@ -146,7 +236,12 @@ mod tests {
         let url = "http://192.168.1.1/";
         let result = ArchivableUrl::parse(url);
         assert!(result.is_err());
-        assert_eq!(result.err(), Some(Error::InvalidUrl(url.to_string())));
+        assert!(matches!(
+            result.err(),
+            Some(Error::InvalidUrl(u, r))
+                if u == url
+                    && r == InvalidReason::PrivateAddress("192.168.1.1".parse().unwrap())
+        ));
     }
 
     #[test]
@@ -154,7 +249,11 @@ mod tests {
         let url = "http://0.0.0.0/";
         let result = ArchivableUrl::parse(url);
         assert!(result.is_err());
-        assert_eq!(result.err(), Some(Error::InvalidUrl(url.to_string())));
+        assert!(matches!(
+            result.err(),
+            Some(Error::InvalidUrl(u, r))
+                if u == url && r == InvalidReason::PrivateAddress("0.0.0.0".parse().unwrap())
+        ));
     }
 
     #[test]
@@ -162,7 +261,11 @@ mod tests {
         let url = "http://localhost.localdomain/";
         let result = ArchivableUrl::parse(url);
         assert!(result.is_err());
-        assert_eq!(result.err(), Some(Error::InvalidUrl(url.to_string())));
+        assert!(matches!(
+            result.err(),
+            Some(Error::InvalidUrl(u, r))
+                if u == url && r == InvalidReason::Localhost
+        ));
     }
 
     #[test]
@@ -170,7 +273,11 @@ mod tests {
         let url = "https://archive.org/some-book";
         let result = ArchivableUrl::parse(url);
         assert!(result.is_err());
-        assert_eq!(result.err(), Some(Error::ExcludedUrl(url.to_string())));
+        assert!(matches!(
+            result.err(),
+            Some(Error::ExcludedUrl(u, r))
+                if u == url && r == InvalidReason::ExcludedDomain { rule: "archive.org".into() }
+        ));
     }
 
     #[test]
@@ -178,7 +285,20 @@ mod tests {
         let url = "https://jstor.org/some-book";
         let result = ArchivableUrl::parse(url);
         assert!(result.is_err());
-        assert_eq!(result.err(), Some(Error::ExcludedUrl(url.to_string())));
+        assert!(matches!(
+            result.err(),
+            Some(Error::ExcludedUrl(u, r))
+                if u == url && r == InvalidReason::ExcludedDomain { rule: "jstor.org".into() }
+        ));
+    }
+
+    #[test]
+    fn subdomains() {
+        assert!(is_subdomain_of("mit.edu", "mit.edu"));
+        assert!(is_subdomain_of("news.MIT.edu.", "mit.edu"));
+        assert!(!is_subdomain_of("summit.edu", "mit.edu"));
+        assert!(!is_subdomain_of("mit.edu", "news.mit.edu"));
+        assert!(!is_subdomain_of("mit.edu.au", "mit.edu"));
     }
 
     #[test]
@@ -187,7 +307,55 @@ mod tests {
             let url = format!("https://{}/some-path", domain);
             let result = ArchivableUrl::parse(&url);
             assert!(result.is_err());
-            assert_eq!(result.err(), Some(Error::ExcludedUrl(url)));
+            assert!(matches!(
+                result.err(),
+                Some(Error::ExcludedUrl(u, r))
+                    if u == url && r == InvalidReason::ExcludedDomain { rule: domain.into() }
+            ));
+        }
+    }
+
+    #[test]
+    fn excluded_subdomain() {
+        let url = "https://news.mit.edu/some-path";
+        let result = ArchivableUrl::parse(url);
+        assert!(matches!(
+            result.err(),
+            Some(Error::ExcludedUrl(u, r))
+                if u == url && r == InvalidReason::ExcludedDomain { rule: "mit.edu".into() }
+        ));
+    }
+
+    #[test]
+    fn excluded_domain_suffix_only() {
+        for url in [
+            "https://summit.edu/",
+            "https://smit.education.org/",
+            "https://notarchive.org/",
+        ] {
+            assert!(ArchivableUrl::parse(url).is_ok(), "{}", url);
         }
     }
+
+    #[test]
+    fn custom_exclusions() {
+        let mut exclusions = ExclusionList::default();
+        exclusions
+            .extend_from_str("# publishers to skip\nexample.com\n\n-jstor.org\n  Example.org.  \n");
+        let policy = Policies::new().with(exclusions.clone());
+        assert!(ArchivableUrl::parse_with_policy("https://jstor.org/", &policy).is_ok());
+        assert!(matches!(
+            ArchivableUrl::parse_with_policy("https://www.example.com/", &policy).err(),
+            Some(Error::ExcludedUrl(u, r))
+                if u == "https://www.example.com/"
+                    && r == InvalidReason::ExcludedDomain { rule: "example.com".into() }
+        ));
+        assert_eq!(exclusions.matching_rule("example.org"), Some("example.org"));
+    }
+
+    #[test]
+    fn empty_exclusions() {
+        let url = "https://archive.org/some-book";
+        assert!(ArchivableUrl::parse_with_policy(url, &Policies::new()).is_ok());
+    }
 }
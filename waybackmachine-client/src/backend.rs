@@ -0,0 +1,289 @@
+use crate::freshness::send_check;
+use crate::ratelimit::{retry_after, Endpoint};
+use crate::verify;
+use crate::{
+    ArchivableUrl, CaptureFailure, ClientConfig, Error, FreshnessCheck, Snapshot,
+    WAYBACK_MACHINE_ARCHIVE_ENDPOINT, WAYBACK_MACHINE_REPLAY_ENDPOINT,
+};
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::Response;
+use reqwest_middleware::ClientWithMiddleware;
+use serde::Deserialize;
+
+/// Progress of a capture requested from an archive
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CaptureStatus {
+    /// The capture is still running, and can be polled with the given job identifier
+    Pending(String),
+    /// The capture is complete, and can be replayed at the given URL
+    Done(String),
+}
+
+/// Web archive able to look up and capture URLs.
+///
+/// Requests are sent with the client's HTTP client, so that they share its
+/// retry settings, rate limits, timeouts and proxy. Lookups and status polls
+/// should be tagged with `Endpoint::Check`, and capture requests with `Endpoint::Save`.
+#[async_trait]
+pub trait ArchiveBackend: Send + Sync {
+    /// Name of the archive, used in reports
+    fn name(&self) -> &str;
+
+    /// Looks up the most recent snapshot of the URL, if any
+    async fn latest_snapshot(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_check: &ArchivableUrl,
+    ) -> Result<Option<Snapshot>, Error>;
+
+    /// Looks up the successful snapshot of the URL closest to the reference date,
+    /// used for dead links. Archives unable to search by date have none.
+    async fn closest_snapshot(
+        &self,
+        _http_client: &ClientWithMiddleware,
+        _to_check: &ArchivableUrl,
+        _reference: NaiveDateTime,
+    ) -> Result<Option<Snapshot>, Error> {
+        Ok(None)
+    }
+
+    /// Returns the URL replaying a snapshot as it was captured, without rewritten
+    /// links or inserted banner, used to verify captures. Archives without such
+    /// a replay have none.
+    fn raw_replay_url(&self, _snapshot_url: &str) -> Option<String> {
+        None
+    }
+
+    /// Returns `true` if the archive fetches the URLs to capture itself, with its
+    /// HTTP client. That client then does not follow redirects, as their locations
+    /// are only validated and archived by the `WaybackMachineClient`.
+    fn fetches_targets(&self) -> bool {
+        false
+    }
+
+    /// Asks the archive to capture the URL
+    async fn request_capture(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_archive: &ArchivableUrl,
+    ) -> Result<CaptureStatus, Error>;
+
+    /// Polls a pending capture of the URL
+    async fn capture_status(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_archive: &ArchivableUrl,
+        job_id: &str,
+    ) -> Result<CaptureStatus, Error>;
+}
+
+/// The Wayback Machine, captured with Save Page Now
+#[derive(Clone, Debug)]
+pub struct WaybackMachine {
+    archive_endpoint: String,
+    freshness_check: FreshnessCheck,
+    authorization: Option<HeaderValue>,
+}
+
+/// Capture job status returned by Save Page Now
+#[derive(Debug, Deserialize)]
+struct SaveStatus {
+    status: String,
+    timestamp: Option<String>,
+    original_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SaveJob {
+    job_id: String,
+}
+
+impl WaybackMachine {
+    /// Constructs a Wayback Machine backend with the given Save Page Now endpoint
+    /// and snapshot lookup service
+    pub fn new(archive_endpoint: impl Into<String>, freshness_check: FreshnessCheck) -> Self {
+        WaybackMachine {
+            archive_endpoint: archive_endpoint.into(),
+            freshness_check,
+            authorization: None,
+        }
+    }
+
+    /// Authenticates captures with the S3-like keys of an archive.org account
+    pub fn credentials(mut self, access_key: &str, secret_key: &str) -> Result<Self, Error> {
+        self.authorization = Some(authorization(access_key, secret_key)?);
+        Ok(self)
+    }
+
+    /// Backend configured with the endpoints and credentials of the client configuration
+    pub(crate) fn from_config(client_config: &ClientConfig) -> Self {
+        WaybackMachine {
+            archive_endpoint: client_config.archive_endpoint.clone(),
+            freshness_check: client_config.freshness_check.clone(),
+            authorization: client_config.authorization.clone(),
+        }
+    }
+
+    /// Builds the Save Page Now request for the given URL.
+    ///
+    /// The target is appended verbatim to the endpoint path, which is how the
+    /// service expects it, including its own query string. Fragments are dropped,
+    /// as they are never sent to the target server and would otherwise be parsed
+    /// as the fragment of the save request.
+    fn archive_request_url(&self, to_archive: &ArchivableUrl) -> String {
+        format!("{}{}", self.archive_endpoint, to_archive.without_fragment())
+    }
+
+    async fn send_save(
+        &self,
+        http_client: &ClientWithMiddleware,
+        url: String,
+    ) -> Result<Response, Error> {
+        let mut request = http_client.get(url).with_extension(Endpoint::Save);
+        // credentials are only ever sent to the archiving service
+        if let Some(authorization) = &self.authorization {
+            request = request.header(AUTHORIZATION, authorization.clone());
+        }
+        request
+            .send()
+            .await
+            .map_err(|e| Error::request(Endpoint::Save, e))
+    }
+}
+
+impl Default for WaybackMachine {
+    fn default() -> Self {
+        WaybackMachine::new(WAYBACK_MACHINE_ARCHIVE_ENDPOINT, FreshnessCheck::default())
+    }
+}
+
+/// Builds the `Authorization` header sent to Save Page Now
+pub(crate) fn authorization(access_key: &str, secret_key: &str) -> Result<HeaderValue, Error> {
+    let mut authorization = HeaderValue::from_str(&format!("LOW {}:{}", access_key, secret_key))
+        .map_err(|_| Error::InvalidConfig("Invalid credentials".into()))?;
+    authorization.set_sensitive(true);
+    Ok(authorization)
+}
+
+/// Reads the cause of a failed capture from the response
+async fn cannot_archive(to_archive: &ArchivableUrl, response: Response) -> Error {
+    let status = response.status();
+    let headers = response.headers().clone();
+    let retry_after = retry_after(&response);
+    let body = response.text().await.unwrap_or_default();
+    Error::CannotArchive {
+        url: to_archive.url.to_string(),
+        status,
+        retry_after,
+        cause: CaptureFailure::parse(status, &headers, &body),
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for WaybackMachine {
+    fn name(&self) -> &str {
+        "Wayback Machine"
+    }
+
+    async fn latest_snapshot(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_check: &ArchivableUrl,
+    ) -> Result<Option<Snapshot>, Error> {
+        self.freshness_check
+            .latest_snapshot(http_client, to_check)
+            .await
+    }
+
+    async fn closest_snapshot(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_check: &ArchivableUrl,
+        reference: NaiveDateTime,
+    ) -> Result<Option<Snapshot>, Error> {
+        self.freshness_check
+            .closest_snapshot(http_client, to_check, reference)
+            .await
+    }
+
+    fn raw_replay_url(&self, snapshot_url: &str) -> Option<String> {
+        verify::raw_replay_url(snapshot_url)
+    }
+
+    /// Sends the URL to Save Page Now. Captures are usually complete once the
+    /// response is received, unless the service answers with a job identifier.
+    async fn request_capture(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_archive: &ArchivableUrl,
+    ) -> Result<CaptureStatus, Error> {
+        let response = self
+            .send_save(http_client, self.archive_request_url(to_archive))
+            .await?;
+        if !response.status().is_success() {
+            return Err(cannot_archive(to_archive, response).await);
+        }
+        let archive_url = response.url().to_string();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await.unwrap_or_default();
+        // the JSON API reports failures with a successful status
+        if let Ok(SaveStatus {
+            status: save_status,
+            ..
+        }) = serde_json::from_str::<SaveStatus>(&body)
+        {
+            if save_status == "error" {
+                return Err(Error::CannotArchive {
+                    url: to_archive.url.to_string(),
+                    status,
+                    retry_after: None,
+                    cause: CaptureFailure::parse(status, &headers, &body),
+                });
+            }
+        }
+        match serde_json::from_str::<SaveJob>(&body) {
+            Ok(job) => Ok(CaptureStatus::Pending(job.job_id)),
+            Err(_) => Ok(CaptureStatus::Done(archive_url)),
+        }
+    }
+
+    async fn capture_status(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_archive: &ArchivableUrl,
+        job_id: &str,
+    ) -> Result<CaptureStatus, Error> {
+        let response =
+            send_check(http_client.get(format!("{}status/{}", self.archive_endpoint, job_id)))
+                .await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await.unwrap_or_default();
+        match serde_json::from_str::<SaveStatus>(&body) {
+            Ok(save_status) if status.is_success() && save_status.status == "pending" => {
+                Ok(CaptureStatus::Pending(job_id.to_string()))
+            }
+            Ok(SaveStatus {
+                status: save_status,
+                timestamp: Some(timestamp),
+                original_url,
+            }) if status.is_success() && save_status == "success" => {
+                Ok(CaptureStatus::Done(format!(
+                    "{}{}/{}",
+                    WAYBACK_MACHINE_REPLAY_ENDPOINT,
+                    timestamp,
+                    original_url.unwrap_or_else(|| to_archive.without_fragment().to_string())
+                )))
+            }
+            _ => Err(Error::CannotArchive {
+                url: to_archive.url.to_string(),
+                status,
+                retry_after: None,
+                cause: CaptureFailure::parse(status, &headers, &body),
+            }),
+        }
+    }
+}
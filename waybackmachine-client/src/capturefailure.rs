@@ -0,0 +1,261 @@
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::fmt;
+
+/// Header the Wayback Machine uses to name the exception that stopped a request
+const RUNTIME_ERROR_HEADER: &str = "X-Archive-Wayback-Runtime-Error";
+
+/// Why Save Page Now refused or failed to capture a URL
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CaptureFailure {
+    /// The site owner asked for the site to be excluded from the Wayback Machine
+    ExcludedBySiteOwner,
+    /// The target's robots.txt does not allow the capture
+    BlockedByRobots,
+    /// The daily capture quota of the account or IP address is used up
+    DailyLimitReached,
+    /// Too many requests or concurrent captures
+    RateLimited,
+    /// The target did not respond in time
+    TargetTimedOut,
+    /// The target's host could not be resolved or connected to
+    TargetUnreachable,
+    /// Save Page Now itself is down or overloaded
+    ServiceUnavailable,
+    /// Any other failure, with the message given by the service if any
+    Unknown(Option<String>),
+}
+
+/// Error response of the Save Page Now JSON API
+#[derive(Debug, Deserialize)]
+struct JsonFailure {
+    status_ext: Option<String>,
+    message: Option<String>,
+}
+
+impl CaptureFailure {
+    /// Returns `true` if capturing the URL again cannot succeed
+    pub fn is_permanent(&self) -> bool {
+        matches!(
+            self,
+            CaptureFailure::ExcludedBySiteOwner | CaptureFailure::BlockedByRobots
+        )
+    }
+
+    /// Returns `true` if capturing the URL again later may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            CaptureFailure::DailyLimitReached
+                | CaptureFailure::RateLimited
+                | CaptureFailure::TargetTimedOut
+                | CaptureFailure::ServiceUnavailable
+        )
+    }
+
+    /// Reads the cause of a failed capture from the response of Save Page Now.
+    ///
+    /// The cause is given by the runtime error header, the `status_ext` code of
+    /// JSON responses, or the explanation in HTML error pages, in that order,
+    /// falling back to the status code.
+    pub(crate) fn parse(status: StatusCode, headers: &HeaderMap, body: &str) -> Self {
+        let runtime_error = headers
+            .get(RUNTIME_ERROR_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if runtime_error.contains("RobotAccessControlException") {
+            return CaptureFailure::BlockedByRobots;
+        }
+        if runtime_error.contains("AdministrativeAccessControlException") {
+            return CaptureFailure::ExcludedBySiteOwner;
+        }
+
+        if let Ok(failure) = serde_json::from_str::<JsonFailure>(body) {
+            return match failure.status_ext.as_deref().and_then(from_status_ext) {
+                Some(cause) => cause,
+                None => from_status(status).unwrap_or(CaptureFailure::Unknown(failure.message)),
+            };
+        }
+
+        from_message(&body.to_lowercase())
+            .or_else(|| from_status(status))
+            .unwrap_or(CaptureFailure::Unknown(None))
+    }
+}
+
+/// Maps the `status_ext` error codes of the Save Page Now 2 API
+fn from_status_ext(status_ext: &str) -> Option<CaptureFailure> {
+    match status_ext {
+        "error:blocked-url" | "error:blocked" => Some(CaptureFailure::ExcludedBySiteOwner),
+        "error:no-access" => Some(CaptureFailure::BlockedByRobots),
+        "error:too-many-daily-captures" => Some(CaptureFailure::DailyLimitReached),
+        "error:too-many-requests" | "error:user-session-limit" => Some(CaptureFailure::RateLimited),
+        "error:browsing-timeout" | "error:read-timeout" | "error:soft-time-limit-exceeded" => {
+            Some(CaptureFailure::TargetTimedOut)
+        }
+        "error:invalid-host-resolution" | "error:cannot-fetch" | "error:bad-gateway" => {
+            Some(CaptureFailure::TargetUnreachable)
+        }
+        "error:service-unavailable" | "error:no-browsers-available" | "error:celery" => {
+            Some(CaptureFailure::ServiceUnavailable)
+        }
+        _ => None,
+    }
+}
+
+/// Recognises the explanations given by the HTML error pages of Save Page Now.
+///
+/// Only its own sentences are matched, as any other page may mention robots.txt
+/// or timeouts, and permanent causes stop the URL from ever being retried.
+fn from_message(body: &str) -> Option<CaptureFailure> {
+    if body.contains("this url has been excluded from the wayback machine") {
+        Some(CaptureFailure::ExcludedBySiteOwner)
+    } else if body.contains("blocked by robots.txt")
+        || body.contains("robots.txt file blocks us")
+        || body.contains("because of the robots.txt")
+    {
+        Some(CaptureFailure::BlockedByRobots)
+    } else if body.contains("captures per day") || body.contains("daily limit") {
+        Some(CaptureFailure::DailyLimitReached)
+    } else if body.contains("limit of active sessions") || body.contains("too many requests") {
+        Some(CaptureFailure::RateLimited)
+    } else if body.contains("the server didn't respond in time") {
+        Some(CaptureFailure::TargetTimedOut)
+    } else if body.contains("couldn't resolve host") || body.contains("connection refused") {
+        Some(CaptureFailure::TargetUnreachable)
+    } else {
+        None
+    }
+}
+
+fn from_status(status: StatusCode) -> Option<CaptureFailure> {
+    match status.as_u16() {
+        429 => Some(CaptureFailure::RateLimited),
+        502..=504 => Some(CaptureFailure::ServiceUnavailable),
+        // Cloudflare's "origin unreachable", also used by Save Page Now
+        523 => Some(CaptureFailure::TargetUnreachable),
+        _ => None,
+    }
+}
+
+impl fmt::Display for CaptureFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureFailure::ExcludedBySiteOwner => write!(f, "excluded by site owner"),
+            CaptureFailure::BlockedByRobots => write!(f, "blocked by robots.txt"),
+            CaptureFailure::DailyLimitReached => write!(f, "daily capture limit reached"),
+            CaptureFailure::RateLimited => write!(f, "rate limited"),
+            CaptureFailure::TargetTimedOut => write!(f, "target timed out"),
+            CaptureFailure::TargetUnreachable => write!(f, "target unreachable"),
+            CaptureFailure::ServiceUnavailable => write!(f, "service unavailable"),
+            CaptureFailure::Unknown(Some(message)) => write!(f, "{}", message),
+            CaptureFailure::Unknown(None) => write!(f, "unknown failure"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn parse(status: u16, body: &str) -> CaptureFailure {
+        CaptureFailure::parse(
+            StatusCode::from_u16(status).unwrap(),
+            &HeaderMap::new(),
+            body,
+        )
+    }
+
+    #[test]
+    fn runtime_error_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            RUNTIME_ERROR_HEADER,
+            HeaderValue::from_static("RobotAccessControlException: Blocked By Robots"),
+        );
+        assert_eq!(
+            CaptureFailure::parse(StatusCode::FORBIDDEN, &headers, ""),
+            CaptureFailure::BlockedByRobots
+        );
+    }
+
+    #[test]
+    fn json_failures() {
+        assert_eq!(
+            parse(
+                200,
+                r#"{"status": "error", "status_ext": "error:too-many-daily-captures", "message": "This URL has been already captured 10 times today."}"#
+            ),
+            CaptureFailure::DailyLimitReached
+        );
+        assert_eq!(
+            parse(
+                200,
+                r#"{"status": "error", "status_ext": "error:filesize-limit", "message": "File size is too big."}"#
+            ),
+            CaptureFailure::Unknown(Some("File size is too big.".into()))
+        );
+    }
+
+    #[test]
+    fn html_failures() {
+        assert_eq!(
+            parse(
+                403,
+                "<html><p>Sorry.</p><p>This URL has been excluded from the Wayback Machine.</p></html>"
+            ),
+            CaptureFailure::ExcludedBySiteOwner
+        );
+        assert_eq!(
+            parse(
+                520,
+                "<p>The server didn't respond in time. Please try again later.</p>"
+            ),
+            CaptureFailure::TargetTimedOut
+        );
+        assert_eq!(
+            parse(
+                429,
+                "<p>You have already reached the limit of active sessions.</p>"
+            ),
+            CaptureFailure::RateLimited
+        );
+    }
+
+    #[test]
+    fn unrelated_messages() {
+        // error pages of other servers are not read as Save Page Now explanations
+        assert_eq!(
+            parse(
+                403,
+                "<p>Forbidden. See our robots.txt for crawling rules.</p>"
+            ),
+            CaptureFailure::Unknown(None)
+        );
+        assert_eq!(
+            parse(500, "<p>The upstream request timed out.</p>"),
+            CaptureFailure::Unknown(None)
+        );
+        assert_eq!(
+            parse(403, "<p>This page is blocked by robots.txt.</p>"),
+            CaptureFailure::BlockedByRobots
+        );
+    }
+
+    #[test]
+    fn status_fallback() {
+        assert_eq!(parse(503, ""), CaptureFailure::ServiceUnavailable);
+        assert_eq!(parse(520, "<html></html>"), CaptureFailure::Unknown(None));
+    }
+
+    #[test]
+    fn permanence() {
+        assert!(CaptureFailure::ExcludedBySiteOwner.is_permanent());
+        assert!(!CaptureFailure::ExcludedBySiteOwner.is_retryable());
+        assert!(CaptureFailure::RateLimited.is_retryable());
+        assert!(!CaptureFailure::Unknown(None).is_permanent());
+        assert!(!CaptureFailure::Unknown(None).is_retryable());
+    }
+}
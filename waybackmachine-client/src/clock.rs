@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+
+/// Source of the current time, used to decide whether a snapshot is recent.
+///
+/// Closures returning a `DateTime<Utc>` implement this trait, which makes it easy
+/// to pin the time in tests.
+pub trait Clock: Send + Sync {
+    /// Returns the current time
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// Clock reading the system time
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> DateTime<Utc> + Send + Sync,
+{
+    fn now(&self) -> DateTime<Utc> {
+        self()
+    }
+}
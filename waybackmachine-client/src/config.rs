@@ -0,0 +1,554 @@
+use crate::backend::{self, ArchiveBackend};
+use crate::clock::{Clock, SystemClock};
+use crate::quorum::Quorum;
+use crate::redirect::{RedirectStrategy, DEFAULT_MAX_REDIRECTS};
+use crate::resolver::Resolver;
+use crate::retry::RetrySettings;
+use crate::soft404::Soft404Detection;
+use crate::{
+    ArchivePolicy, Error, FreshnessCheck, FreshnessRules, Policies, RateLimits,
+    WAYBACK_MACHINE_ARCHIVE_ENDPOINT,
+};
+use chrono::{NaiveDateTime, TimeDelta};
+use reqwest::header::HeaderValue;
+use reqwest::Proxy;
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+/// Default threshold for considering an archive as recent, in days.
+/// URLs with archives older than this threshold will be re-archived.
+const DEFAULT_ARCHIVE_THRESHOLD_DAYS: i64 = 30;
+
+/// Default wait between two polls of a pending capture
+const DEFAULT_CAPTURE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Default time after which a pending capture is no longer polled
+const DEFAULT_CAPTURE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// User-agent to make requests from
+const DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Fedora; Linux x86_64; rv:40.0) Gecko/20100101 Firefox/40.0";
+
+/// Configuration for the Wayback Machine client
+pub struct ClientConfig {
+    pub(crate) archive_endpoint: String,
+    pub(crate) freshness_check: FreshnessCheck,
+    pub(crate) target_retry: RetrySettings,
+    pub(crate) check_retry: RetrySettings,
+    pub(crate) save_retry: RetrySettings,
+    pub(crate) archive_threshold: TimeDelta,
+    pub(crate) reference_window: Option<TimeDelta>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) freshness_rules: FreshnessRules,
+    pub(crate) policy: Arc<dyn ArchivePolicy>,
+    pub(crate) max_redirects: usize,
+    pub(crate) redirect_strategy: RedirectStrategy,
+    pub(crate) closest_for_dead_links: bool,
+    pub(crate) soft_404_detection: Soft404Detection,
+    pub(crate) verify_captures: bool,
+    pub(crate) resolver: Option<Arc<dyn Resolver>>,
+    pub(crate) user_agent: HeaderValue,
+    pub(crate) rate_limits: RateLimits,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) proxy: Option<Proxy>,
+    pub(crate) authorization: Option<HeaderValue>,
+    pub(crate) backends: Vec<Arc<dyn ArchiveBackend>>,
+    pub(crate) quorum: Quorum,
+    pub(crate) capture_poll_interval: Duration,
+    pub(crate) capture_timeout: Duration,
+}
+
+/// Builder for `ClientConfig`.
+///
+/// Every option is validated in `build`, so that a misconfiguration is reported
+/// as an `Error::InvalidConfig` instead of a panic.
+#[derive(Default)]
+pub struct ClientConfigBuilder {
+    archive_endpoint: Option<String>,
+    freshness_check: Option<FreshnessCheck>,
+    max_request_retries: Option<u32>,
+    target_retry: Option<RetrySettings>,
+    check_retry: Option<RetrySettings>,
+    save_retry: Option<RetrySettings>,
+    archive_threshold: Option<Result<TimeDelta, Error>>,
+    reference_window: Option<TimeDelta>,
+    clock: Option<Arc<dyn Clock>>,
+    freshness_rules: Option<FreshnessRules>,
+    policy: Option<Arc<dyn ArchivePolicy>>,
+    max_redirects: Option<usize>,
+    redirect_strategy: Option<RedirectStrategy>,
+    closest_for_dead_links: bool,
+    soft_404_detection: Soft404Detection,
+    verify_captures: bool,
+    resolver: Option<Arc<dyn Resolver>>,
+    user_agent: Option<String>,
+    rate_limits: Option<RateLimits>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<String>,
+    credentials: Option<(String, String)>,
+    backends: Vec<Arc<dyn ArchiveBackend>>,
+    quorum: Option<Quorum>,
+    capture_poll_interval: Option<Duration>,
+    capture_timeout: Option<Duration>,
+}
+
+impl ClientConfig {
+    /// Returns a builder to construct a `ClientConfig`
+    pub fn builder() -> ClientConfigBuilder {
+        ClientConfigBuilder::default()
+    }
+}
+
+impl Default for ClientConfig {
+    /// Constructs a default `ClientConfig` with default retry policy and user agent
+    fn default() -> Self {
+        ClientConfig::builder()
+            .build()
+            .expect("Default configuration is valid")
+    }
+}
+
+/// Options applying to a single archive request, overriding the client configuration
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveOptions {
+    /// Age after which an archive is no longer considered recent,
+    /// taking precedence over any freshness rule
+    pub archive_threshold: Option<TimeDelta>,
+    /// URLs of the redirect chain to archive, taking precedence over the
+    /// configured strategy
+    pub redirect_strategy: Option<RedirectStrategy>,
+    /// Date (UTC) of reference for the request, such as the publication date of
+    /// the citing document: snapshots of dead URLs are looked up closest to it,
+    /// and thresholds relative to publication are counted back from it
+    pub reference_date: Option<NaiveDateTime>,
+}
+
+impl ClientConfigBuilder {
+    /// Sets the Save Page Now endpoint
+    pub fn archive_endpoint(mut self, archive_endpoint: impl Into<String>) -> Self {
+        self.archive_endpoint = Some(archive_endpoint.into());
+        self
+    }
+
+    /// Sets the CDX server endpoint used to look up existing snapshots
+    pub fn check_endpoint(mut self, check_endpoint: impl Into<String>) -> Self {
+        self.freshness_check = Some(FreshnessCheck::Cdx(check_endpoint.into()));
+        self
+    }
+
+    /// Sets the service used to look up existing snapshots
+    pub fn freshness_check(mut self, freshness_check: FreshnessCheck) -> Self {
+        self.freshness_check = Some(freshness_check);
+        self
+    }
+
+    /// Sets the maximum number of retries of a failed request, for the kinds of
+    /// requests without their own retry settings
+    pub fn max_request_retries(mut self, max_request_retries: u32) -> Self {
+        self.max_request_retries = Some(max_request_retries);
+        self
+    }
+
+    /// Sets how requests to the URLs being archived, made to follow their redirects,
+    /// are retried
+    pub fn target_retry(mut self, target_retry: RetrySettings) -> Self {
+        self.target_retry = Some(target_retry);
+        self
+    }
+
+    /// Sets how snapshot lookups are retried
+    pub fn check_retry(mut self, check_retry: RetrySettings) -> Self {
+        self.check_retry = Some(check_retry);
+        self
+    }
+
+    /// Sets how Save Page Now requests are retried
+    pub fn save_retry(mut self, save_retry: RetrySettings) -> Self {
+        self.save_retry = Some(save_retry);
+        self
+    }
+
+    /// Sets the age, in days, after which an archive is no longer considered recent
+    pub fn archive_threshold_days(mut self, archive_threshold_days: i64) -> Self {
+        self.archive_threshold =
+            Some(TimeDelta::try_days(archive_threshold_days).ok_or_else(|| {
+                Error::InvalidConfig(format!(
+                    "Archive threshold out of range: {} days",
+                    archive_threshold_days
+                ))
+            }));
+        self
+    }
+
+    /// Sets the age after which an archive is no longer considered recent
+    pub fn archive_threshold(mut self, archive_threshold: TimeDelta) -> Self {
+        self.archive_threshold = Some(Ok(archive_threshold));
+        self
+    }
+
+    /// Considers a URL recently archived if it has a snapshot within the given
+    /// time of the reference date of the request, before or after it, instead of
+    /// a snapshot younger than the archive threshold. Requests without a reference
+    /// date still use the archive threshold.
+    pub fn reference_window(mut self, reference_window: TimeDelta) -> Self {
+        self.reference_window = Some(reference_window);
+        self
+    }
+
+    /// Sets per-domain rules overriding the archive threshold
+    pub fn freshness_rules(mut self, freshness_rules: FreshnessRules) -> Self {
+        self.freshness_rules = Some(freshness_rules);
+        self
+    }
+
+    /// Sets the policy deciding which URLs may be archived, usually a `Policies` set.
+    /// Defaults to `Policies::default()`.
+    pub fn policy(mut self, policy: impl ArchivePolicy + 'static) -> Self {
+        self.policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Sets the maximum number of redirects followed to find the final location of a URL
+    pub fn max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = Some(max_redirects);
+        self
+    }
+
+    /// Sets which URLs of a redirect chain are checked and archived:
+    /// the original URL (default), its final location, or both
+    pub fn redirect_strategy(mut self, redirect_strategy: RedirectStrategy) -> Self {
+        self.redirect_strategy = Some(redirect_strategy);
+        self
+    }
+
+    /// Reports the successful snapshot closest to the reference date for URLs that
+    /// are dead (DNS or connection failure, `404` or `410`), instead of archiving the error
+    pub fn closest_for_dead_links(mut self, closest_for_dead_links: bool) -> Self {
+        self.closest_for_dead_links = closest_for_dead_links;
+        self
+    }
+
+    /// Checks URLs answering successfully for signs of a missing page (soft-404),
+    /// and reports or skips them. Defaults to `Soft404Detection::Off`.
+    pub fn soft_404_detection(mut self, soft_404_detection: Soft404Detection) -> Self {
+        self.soft_404_detection = soft_404_detection;
+        self
+    }
+
+    /// Compares each new capture, replayed as it was archived, with the live page,
+    /// reporting the `CaptureVerification` in its `ArchiveOutcome`
+    pub fn verify_captures(mut self, verify_captures: bool) -> Self {
+        self.verify_captures = verify_captures;
+        self
+    }
+
+    /// Resolves host names with the resolver when connecting, and refuses to
+    /// connect to private or reserved addresses. URLs only resolving to such
+    /// addresses are rejected. With a proxy, URLs are resolved by the proxy, and
+    /// only its own host name is checked.
+    pub fn resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Sets the clock the archive threshold is evaluated against
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Sets the user agent to make requests from
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    /// Sets the client-side limits on requests sent to the Wayback Machine
+    pub fn rate_limits(mut self, rate_limits: RateLimits) -> Self {
+        self.rate_limits = Some(rate_limits);
+        self
+    }
+
+    /// Sets the timeout of each request, from connection until the body is read
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the timeout for establishing a connection
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
+    /// Sends every request through the given proxy
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// Sets the archive.org S3-like API keys used to authenticate Save Page Now requests.
+    ///
+    /// https://archive.org/account/s3.php
+    pub fn credentials(
+        mut self,
+        access_key: impl Into<String>,
+        secret_key: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((access_key.into(), secret_key.into()));
+        self
+    }
+
+    /// Adds an archive URLs are looked up in and captured by. Without any, the
+    /// Wayback Machine configured with the endpoints and credentials of this builder
+    /// is used.
+    pub fn backend(mut self, backend: impl ArchiveBackend + 'static) -> Self {
+        self.backends.push(Arc::new(backend));
+        self
+    }
+
+    /// Sets how many archives must preserve a URL for it to be considered
+    /// preserved. Defaults to `Quorum::All`.
+    pub fn quorum(mut self, quorum: Quorum) -> Self {
+        self.quorum = Some(quorum);
+        self
+    }
+
+    /// Sets the wait between two polls of a pending capture
+    pub fn capture_poll_interval(mut self, capture_poll_interval: Duration) -> Self {
+        self.capture_poll_interval = Some(capture_poll_interval);
+        self
+    }
+
+    /// Sets the time after which a pending capture is no longer polled, and
+    /// reported as `Error::CapturePending`
+    pub fn capture_timeout(mut self, capture_timeout: Duration) -> Self {
+        self.capture_timeout = Some(capture_timeout);
+        self
+    }
+
+    /// Validates the options and constructs the `ClientConfig`
+    pub fn build(self) -> Result<ClientConfig, Error> {
+        let archive_endpoint = self
+            .archive_endpoint
+            .unwrap_or_else(|| WAYBACK_MACHINE_ARCHIVE_ENDPOINT.into());
+        Url::parse(&archive_endpoint).map_err(|e| {
+            Error::InvalidConfig(format!(
+                "Invalid archive endpoint {}: {}",
+                archive_endpoint, e
+            ))
+        })?;
+
+        let freshness_check = self.freshness_check.unwrap_or_default();
+        Url::parse(freshness_check.endpoint()).map_err(|e| {
+            Error::InvalidConfig(format!(
+                "Invalid check endpoint {}: {}",
+                freshness_check.endpoint(),
+                e
+            ))
+        })?;
+
+        let archive_threshold = match self.archive_threshold {
+            Some(archive_threshold) => archive_threshold?,
+            None => TimeDelta::try_days(DEFAULT_ARCHIVE_THRESHOLD_DAYS).unwrap(),
+        };
+        if archive_threshold < TimeDelta::zero() {
+            return Err(Error::InvalidConfig(format!(
+                "Archive threshold cannot be negative: {}",
+                archive_threshold
+            )));
+        }
+
+        let user_agent = self.user_agent.unwrap_or_else(|| DEFAULT_USER_AGENT.into());
+        let user_agent = HeaderValue::from_str(&user_agent)
+            .map_err(|_| Error::InvalidConfig(format!("Invalid user agent: {}", user_agent)))?;
+
+        let proxy = self
+            .proxy
+            .map(|proxy| {
+                Proxy::all(&proxy)
+                    .map_err(|e| Error::InvalidConfig(format!("Invalid proxy {}: {}", proxy, e)))
+            })
+            .transpose()?;
+
+        let authorization = self
+            .credentials
+            .map(|(access_key, secret_key)| backend::authorization(&access_key, &secret_key))
+            .transpose()?;
+
+        let quorum = self.quorum.unwrap_or_default();
+        quorum
+            .validate(self.backends.len().max(1))
+            .map_err(Error::InvalidConfig)?;
+
+        let default_retry = match self.max_request_retries {
+            Some(max_retries) => RetrySettings {
+                max_retries,
+                ..Default::default()
+            },
+            None => RetrySettings::default(),
+        };
+        let [target_retry, check_retry, save_retry] =
+            [self.target_retry, self.check_retry, self.save_retry]
+                .map(|retry| retry.unwrap_or_else(|| default_retry.clone()));
+        for retry in [&target_retry, &check_retry, &save_retry] {
+            retry.validate().map_err(Error::InvalidConfig)?;
+        }
+
+        Ok(ClientConfig {
+            archive_endpoint,
+            freshness_check,
+            target_retry,
+            check_retry,
+            save_retry,
+            archive_threshold,
+            reference_window: self.reference_window,
+            clock: self.clock.unwrap_or_else(|| Arc::new(SystemClock)),
+            freshness_rules: self.freshness_rules.unwrap_or_default(),
+            policy: self.policy.unwrap_or_else(|| Arc::new(Policies::default())),
+            max_redirects: self.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS),
+            redirect_strategy: self.redirect_strategy.unwrap_or_default(),
+            closest_for_dead_links: self.closest_for_dead_links,
+            soft_404_detection: self.soft_404_detection,
+            verify_captures: self.verify_captures,
+            resolver: self.resolver,
+            user_agent,
+            rate_limits: self.rate_limits.unwrap_or_default(),
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+            proxy,
+            authorization,
+            backends: self.backends,
+            quorum,
+            capture_poll_interval: self
+                .capture_poll_interval
+                .unwrap_or(DEFAULT_CAPTURE_POLL_INTERVAL),
+            capture_timeout: self.capture_timeout.unwrap_or(DEFAULT_CAPTURE_TIMEOUT),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config() {
+        assert!(ClientConfig::builder().build().is_ok());
+    }
+
+    #[test]
+    fn custom_config() {
+        let client_config = ClientConfig::builder()
+            .archive_endpoint("http://127.0.0.1:8080/save/")
+            .check_endpoint("http://127.0.0.1:8080/cdx/search/cdx?url=")
+            .max_request_retries(2)
+            .archive_threshold_days(7)
+            .user_agent("TestUserAgent")
+            .timeout(Duration::from_secs(30))
+            .connect_timeout(Duration::from_secs(5))
+            .proxy("http://127.0.0.1:3128")
+            .credentials("access", "secret")
+            .build()
+            .unwrap();
+        assert_eq!(
+            client_config.archive_endpoint,
+            "http://127.0.0.1:8080/save/"
+        );
+        assert_eq!(
+            client_config.freshness_check,
+            FreshnessCheck::Cdx("http://127.0.0.1:8080/cdx/search/cdx?url=".into())
+        );
+        assert_eq!(client_config.user_agent, "TestUserAgent");
+        assert_eq!(client_config.timeout, Some(Duration::from_secs(30)));
+        assert!(client_config.proxy.is_some());
+        assert_eq!(
+            client_config.authorization,
+            Some(HeaderValue::from_static("LOW access:secret"))
+        );
+    }
+
+    #[test]
+    fn invalid_archive_endpoint() {
+        let result = ClientConfig::builder()
+            .archive_endpoint("not a url")
+            .build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn invalid_check_endpoint() {
+        let result = ClientConfig::builder()
+            .freshness_check(FreshnessCheck::Availability("/wayback/available".into()))
+            .build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn archive_threshold_out_of_range() {
+        let result = ClientConfig::builder()
+            .archive_threshold_days(i64::MAX)
+            .build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn negative_archive_threshold() {
+        let result = ClientConfig::builder()
+            .archive_threshold(TimeDelta::try_hours(-1).unwrap())
+            .build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn invalid_user_agent() {
+        let result = ClientConfig::builder().user_agent("Test\nAgent").build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn invalid_proxy() {
+        let result = ClientConfig::builder().proxy("not a proxy").build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn unreachable_quorum() {
+        // without any backend, only the Wayback Machine is used
+        let result = ClientConfig::builder().quorum(Quorum::AtLeast(2)).build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+        let result = ClientConfig::builder()
+            .backend(crate::PermaCc::new("key"))
+            .backend(crate::WaybackMachine::default())
+            .quorum(Quorum::AtLeast(2))
+            .build();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn invalid_retry_bounds() {
+        let result = ClientConfig::builder()
+            .check_retry(RetrySettings {
+                min_interval: Duration::from_secs(10),
+                max_interval: Duration::from_secs(1),
+                ..Default::default()
+            })
+            .build();
+        assert!(matches!(result, Err(Error::InvalidConfig(_))));
+    }
+
+    #[test]
+    fn retry_settings_per_kind() {
+        let config = ClientConfig::builder()
+            .max_request_retries(2)
+            .save_retry(RetrySettings::none())
+            .build()
+            .unwrap();
+        assert_eq!(config.target_retry.max_retries, 2);
+        assert_eq!(config.check_retry.max_retries, 2);
+        assert_eq!(config.save_retry.max_retries, 0);
+    }
+}
@@ -1,43 +1,435 @@
+use crate::ratelimit::Endpoint;
+use crate::resolver::ResolveError;
+use crate::{ArchiveReport, CaptureFailure, Soft404, TargetStatus};
+use reqwest::StatusCode;
+use std::error::Error as StdError;
 use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Why a URL is refused for archiving
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InvalidReason {
+    /// The input is not a valid absolute URL
+    Unparseable(url::ParseError),
+    /// The scheme is not one of the allowed ones, e.g. `mailto:` or `ftp:`
+    UnsupportedScheme(String),
+    /// The URL has no host, e.g. `data:` URLs
+    MissingHost,
+    /// The host is a name for the local machine
+    Localhost,
+    /// The host is an IP address outside the public unicast ranges
+    PrivateAddress(IpAddr),
+    /// The host name resolves to an IP address outside the public unicast ranges
+    ResolvesToPrivateAddress(IpAddr),
+    /// The domain, or one of its parents, is in the `ExclusionList`
+    ExcludedDomain { rule: String },
+    /// The URL matches a pattern of the `DenyList`
+    DeniedPattern { pattern: String },
+    /// The URL matches none of the patterns of the `AllowList`
+    NotAllowed,
+    /// Reason given by a custom `ArchivePolicy`
+    Other(String),
+}
+
+impl fmt::Display for InvalidReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidReason::Unparseable(err) => write!(f, "unparseable URL: {}", err),
+            InvalidReason::UnsupportedScheme(scheme) => write!(f, "unsupported scheme {}", scheme),
+            InvalidReason::MissingHost => write!(f, "missing host"),
+            InvalidReason::Localhost => write!(f, "local host"),
+            InvalidReason::PrivateAddress(ip) => write!(f, "private address {}", ip),
+            InvalidReason::ResolvesToPrivateAddress(ip) => {
+                write!(f, "resolves to private address {}", ip)
+            }
+            InvalidReason::ExcludedDomain { rule } => write!(f, "excluded domain {}", rule),
+            InvalidReason::DeniedPattern { pattern } => write!(f, "matches pattern {}", pattern),
+            InvalidReason::NotAllowed => write!(f, "not in allow list"),
+            InvalidReason::Other(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Boxed error kept as the `source` of an `Error`
+pub type BoxError = Box<dyn StdError + Send + Sync>;
 
 /// Errors that can occur during archiving.
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug)]
 pub enum Error {
-    InvalidUrl(String),
-    RequestFailed(String),
-    CannotArchive(String, String),
-    CannotCheckArchive(String),
+    /// The URL is not a valid archiving target
+    InvalidUrl(String, InvalidReason),
+    /// The client configuration is invalid
+    InvalidConfig(String),
+    /// A request could not be sent, or its response could not be read
+    RequestFailed(RequestError),
+    /// A lookup service answered with an error status
+    ServiceRefused {
+        endpoint: Endpoint,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    /// The archive refused or failed to capture the URL, and no recent snapshot exists
+    CannotArchive {
+        url: String,
+        status: StatusCode,
+        retry_after: Option<Duration>,
+        cause: CaptureFailure,
+    },
+    /// The capture was accepted, but had not completed when the client stopped polling it
+    CapturePending { url: String, job_id: String },
+    /// Fewer archives than the quorum preserved the URL: the report holds the
+    /// outcome in each archive of every URL of the redirect chain
+    QuorumNotReached {
+        url: String,
+        required: usize,
+        report: Box<ArchiveReport>,
+    },
+    /// The URL is dead, and no successful snapshot of it exists
+    DeadUrl { url: String, status: TargetStatus },
+    /// The URL answers successfully but likely leads to a missing page, so it
+    /// was not archived
+    LikelySoft404 { url: String, soft_404: Soft404 },
+    /// A local archive could not be read or written
+    Storage { path: PathBuf, source: io::Error },
+    /// The lookup service answered with a response that cannot be understood
+    CannotCheckArchive {
+        reason: String,
+        source: Option<BoxError>,
+    },
+    /// No snapshot of the URL is recent enough
     NoRecentArchive(String),
-    ExcludedUrl(String),
+    /// The URL is valid, but must not be archived
+    ExcludedUrl(String, InvalidReason),
 }
 
-impl fmt::Display for Error {
+/// Failure to complete a request
+#[derive(Debug)]
+pub struct RequestError {
+    /// Endpoint the request was sent to
+    pub endpoint: Endpoint,
+    /// What went wrong
+    pub kind: RequestErrorKind,
+    source: BoxError,
+}
+
+/// Category of a `RequestError`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RequestErrorKind {
+    /// The request or the response body timed out
+    Timeout,
+    /// The host name could not be resolved
+    Dns,
+    /// The TLS handshake failed, e.g. because of an invalid certificate
+    Tls,
+    /// The connection could not be established
+    Connect,
+    /// The response body could not be read
+    Body,
+    /// Any other failure, including errors raised by middlewares
+    Other,
+}
+
+impl Error {
+    /// Wraps a failed request to the given endpoint
+    pub(crate) fn request(endpoint: Endpoint, err: impl Into<reqwest_middleware::Error>) -> Self {
+        Error::RequestFailed(RequestError::new(endpoint, err.into()))
+    }
+
+    /// Constructs a `CannotCheckArchive` caused by the given error
+    pub(crate) fn cannot_check(reason: impl Into<String>, source: impl Into<BoxError>) -> Self {
+        Error::CannotCheckArchive {
+            reason: reason.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// Constructs a `Storage` error for the given file
+    pub(crate) fn storage(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        Error::Storage {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Returns the status code the service answered with, if any
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::ServiceRefused { status, .. } | Error::CannotArchive { status, .. } => {
+                Some(*status)
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns why Save Page Now failed to capture the URL, if it did
+    pub fn capture_failure(&self) -> Option<&CaptureFailure> {
+        match self {
+            Error::CannotArchive { cause, .. } => Some(cause),
+            _ => None,
+        }
+    }
+
+    /// Returns how long the service asked to wait before sending another request, if it did
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::ServiceRefused { retry_after, .. }
+            | Error::CannotArchive { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl RequestErrorKind {
+    /// Classifies a failed request
+    pub(crate) fn of(err: &reqwest_middleware::Error) -> Self {
+        match err {
+            reqwest_middleware::Error::Reqwest(err) => classify(err),
+            reqwest_middleware::Error::Middleware(_) => RequestErrorKind::Other,
+        }
+    }
+}
+
+impl RequestError {
+    fn new(endpoint: Endpoint, err: reqwest_middleware::Error) -> Self {
+        match err {
+            reqwest_middleware::Error::Reqwest(err) => RequestError {
+                endpoint,
+                kind: classify(&err),
+                source: Box::new(err),
+            },
+            reqwest_middleware::Error::Middleware(err) => RequestError {
+                endpoint,
+                kind: RequestErrorKind::Other,
+                source: err.into(),
+            },
+        }
+    }
+}
+
+/// Finds the cause of a `reqwest` error, which is only exposed through its source chain.
+///
+/// Resolution failures are only told apart when the client resolves host names
+/// with a `HostResolver`.
+fn classify(err: &reqwest::Error) -> RequestErrorKind {
+    if err.is_timeout() {
+        return RequestErrorKind::Timeout;
+    }
+    let mut source = err.source();
+    while let Some(cause) = source {
+        if cause.is::<ResolveError>() {
+            return RequestErrorKind::Dns;
+        }
+        if cause.is::<native_tls::Error>() {
+            return RequestErrorKind::Tls;
+        }
+        if cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|err| err.kind() == io::ErrorKind::TimedOut)
+        {
+            return RequestErrorKind::Timeout;
+        }
+        source = cause.source();
+    }
+    if err.is_connect() {
+        RequestErrorKind::Connect
+    } else if err.is_body() || err.is_decode() {
+        RequestErrorKind::Body
+    } else {
+        RequestErrorKind::Other
+    }
+}
+
+impl fmt::Display for Endpoint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::InvalidUrl(input) => write!(f, "Invalid URL: {}", input),
-            Error::RequestFailed(err) => write!(f, "Request failed: {}", err),
-            Error::CannotArchive(code, url) => write!(f, "Failed ({}): {}", code, url),
-            Error::CannotCheckArchive(error) => write!(f, "Failed to get archive: {}", error),
-            Error::NoRecentArchive(url) => write!(f, "No recent archive exists: {}", url),
-            Error::ExcludedUrl(url) => write!(f, "Excluded URL: {}", url),
+            Endpoint::Target => write!(f, "target"),
+            Endpoint::Save => write!(f, "Save Page Now"),
+            Endpoint::Check => write!(f, "snapshot lookup"),
         }
     }
 }
 
-impl From<reqwest_middleware::Error> for Error {
-    fn from(err: reqwest_middleware::Error) -> Self {
-        Error::RequestFailed(err.to_string())
+impl fmt::Display for RequestErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestErrorKind::Timeout => write!(f, "timeout"),
+            RequestErrorKind::Dns => write!(f, "DNS failure"),
+            RequestErrorKind::Tls => write!(f, "TLS failure"),
+            RequestErrorKind::Connect => write!(f, "connection failure"),
+            RequestErrorKind::Body => write!(f, "invalid response body"),
+            RequestErrorKind::Other => write!(f, "request failure"),
+        }
     }
 }
 
-impl From<reqwest::Error> for Error {
-    fn from(err: reqwest::Error) -> Self {
-        Error::RequestFailed(err.to_string())
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} request failed ({})", self.endpoint, self.kind)
+    }
+}
+
+impl StdError for RequestError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidUrl(input, reason) => write!(f, "Invalid URL ({}): {}", reason, input),
+            Error::InvalidConfig(reason) => write!(f, "Invalid configuration: {}", reason),
+            Error::RequestFailed(err) => write!(f, "{}", err),
+            Error::ServiceRefused {
+                endpoint, status, ..
+            } => write!(f, "{} refused the request ({})", endpoint, status),
+            Error::CannotArchive {
+                url, status, cause, ..
+            } => write!(f, "Failed ({}, {}): {}", status, cause, url),
+            Error::CapturePending { url, job_id } => {
+                write!(f, "Capture still pending ({}): {}", job_id, url)
+            }
+            Error::QuorumNotReached {
+                url,
+                required,
+                report,
+            } => {
+                let archives: Vec<&str> = report
+                    .failures()
+                    .filter(|(failed, _, _)| failed.as_str() == url)
+                    .map(|(_, archive, _)| archive)
+                    .collect();
+                write!(
+                    f,
+                    "Not preserved in {} archives (failed in {}): {}",
+                    required,
+                    archives.join(", "),
+                    url
+                )
+            }
+            Error::DeadUrl { url, status } => {
+                write!(f, "Dead link without any snapshot ({}): {}", status, url)
+            }
+            Error::LikelySoft404 { url, soft_404 } => {
+                write!(f, "Likely soft 404 ({}): {}", soft_404, url)
+            }
+            Error::Storage { path, source } => {
+                write!(f, "Cannot access {}: {}", path.display(), source)
+            }
+            Error::CannotCheckArchive { reason, .. } => {
+                write!(f, "Failed to get archive: {}", reason)
+            }
+            Error::NoRecentArchive(url) => write!(f, "No recent archive exists: {}", url),
+            Error::ExcludedUrl(url, reason) => write!(f, "Excluded URL ({}): {}", reason, url),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::RequestFailed(err) => err.source(),
+            Error::Storage { source, .. } => Some(source),
+            Error::CannotCheckArchive {
+                source: Some(source),
+                ..
+            } => Some(source.as_ref()),
+            _ => None,
+        }
     }
 }
 
 impl From<chrono::ParseError> for Error {
     fn from(err: chrono::ParseError) -> Self {
-        Error::CannotCheckArchive(err.to_string())
+        Error::cannot_check("invalid snapshot timestamp", err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::HostResolver;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    async fn failed_request(client: &reqwest::Client, url: &str) -> Error {
+        let err = match client.get(url).send().await {
+            Ok(response) => response.text().await.err().unwrap(),
+            Err(err) => err,
+        };
+        Error::request(Endpoint::Check, err)
+    }
+
+    /// Client resolving host names like the `WaybackMachineClient`
+    fn client() -> reqwest::Client {
+        reqwest::Client::builder()
+            .dns_resolver(Arc::new(HostResolver::system()))
+            .build()
+            .unwrap()
+    }
+
+    fn kind(err: &Error) -> RequestErrorKind {
+        match err {
+            Error::RequestFailed(err) => err.kind,
+            _ => panic!("not a request failure: {}", err),
+        }
+    }
+
+    #[tokio::test]
+    async fn connection_refused() {
+        // nothing listens on the discard port
+        let err = failed_request(&client(), "http://127.0.0.1:9/").await;
+        assert_eq!(kind(&err), RequestErrorKind::Connect);
+        assert!(StdError::source(&err).is_some());
+    }
+
+    #[tokio::test]
+    async fn dns_failure() {
+        let err = failed_request(&client(), "http://nonexistent.invalid/").await;
+        assert_eq!(kind(&err), RequestErrorKind::Dns);
+    }
+
+    #[tokio::test]
+    async fn tls_failure() {
+        // the server only speaks plain HTTP
+        let server = mockito::Server::new_async().await;
+        let url = format!("https://{}/", server.host_with_port());
+        let err = failed_request(&client(), &url).await;
+        assert_eq!(kind(&err), RequestErrorKind::Tls);
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", "/")
+            .with_chunked_body(|writer| {
+                std::thread::sleep(Duration::from_millis(500));
+                writer.write_all(b"too late")
+            })
+            .create_async()
+            .await;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let err = failed_request(&client, &server.url()).await;
+        assert_eq!(kind(&err), RequestErrorKind::Timeout);
+    }
+
+    #[test]
+    fn source_chain() {
+        let err: Error = chrono::NaiveDateTime::parse_from_str("not a date", "%Y")
+            .unwrap_err()
+            .into();
+        assert!(matches!(err, Error::CannotCheckArchive { .. }));
+        assert!(StdError::source(&err).is_some());
+        assert_eq!(
+            err.to_string(),
+            "Failed to get archive: invalid snapshot timestamp"
+        );
     }
 }
@@ -0,0 +1,305 @@
+use crate::memento;
+use crate::ratelimit::{retry_after, Endpoint};
+use crate::snapshot::WAYBACK_TIMESTAMP_FORMAT;
+use crate::{
+    ArchivableUrl, Error, Snapshot, WAYBACK_MACHINE_AVAILABILITY_ENDPOINT,
+    WAYBACK_MACHINE_CHECK_ENDPOINT, WAYBACK_MACHINE_TIMEGATE_ENDPOINT,
+};
+use chrono::NaiveDateTime;
+use reqwest::Response;
+use reqwest_middleware::{ClientWithMiddleware, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use url::Url;
+
+/// Service used to look up the latest snapshot of a URL
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FreshnessCheck {
+    /// Wayback CDX server
+    ///
+    /// https://github.com/internetarchive/wayback/tree/master/wayback-cdx-server
+    Cdx(String),
+    /// Wayback Availability JSON API
+    ///
+    /// https://archive.org/help/wayback_api.php
+    Availability(String),
+    /// Memento TimeGate, as described in RFC 7089
+    TimeGate(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct CdxResponse(Vec<Vec<String>>);
+
+#[derive(Debug, Deserialize)]
+struct AvailabilityResponse {
+    archived_snapshots: ArchivedSnapshots,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchivedSnapshots {
+    closest: Option<ClosestSnapshot>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ClosestSnapshot {
+    available: bool,
+    url: String,
+    timestamp: String,
+    status: Option<String>,
+}
+
+impl FreshnessCheck {
+    /// Wayback CDX server at its default endpoint
+    pub fn cdx() -> Self {
+        FreshnessCheck::Cdx(WAYBACK_MACHINE_CHECK_ENDPOINT.into())
+    }
+
+    /// Wayback Availability API at its default endpoint
+    pub fn availability() -> Self {
+        FreshnessCheck::Availability(WAYBACK_MACHINE_AVAILABILITY_ENDPOINT.into())
+    }
+
+    /// Wayback Machine TimeGate at its default endpoint
+    pub fn timegate() -> Self {
+        FreshnessCheck::TimeGate(WAYBACK_MACHINE_TIMEGATE_ENDPOINT.into())
+    }
+
+    /// Returns the configured endpoint
+    pub fn endpoint(&self) -> &str {
+        match self {
+            FreshnessCheck::Cdx(endpoint)
+            | FreshnessCheck::Availability(endpoint)
+            | FreshnessCheck::TimeGate(endpoint) => endpoint,
+        }
+    }
+
+    /// Looks up the most recent snapshot of the given URL, if any
+    pub(crate) async fn latest_snapshot(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_check: &ArchivableUrl,
+    ) -> Result<Option<Snapshot>, Error> {
+        match self {
+            FreshnessCheck::Cdx(endpoint) => cdx_snapshot(http_client, endpoint, to_check).await,
+            FreshnessCheck::Availability(endpoint) => {
+                availability_snapshot(http_client, endpoint, to_check).await
+            }
+            FreshnessCheck::TimeGate(endpoint) => {
+                memento::timegate_memento(http_client, endpoint, to_check, None).await
+            }
+        }
+    }
+
+    /// Looks up the successful snapshot of the given URL closest to the reference
+    /// date, skipping captures of errors and redirects where the service allows it
+    pub(crate) async fn closest_snapshot(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_check: &ArchivableUrl,
+        reference: NaiveDateTime,
+    ) -> Result<Option<Snapshot>, Error> {
+        let timestamp = reference.format(WAYBACK_TIMESTAMP_FORMAT).to_string();
+        match self {
+            FreshnessCheck::Cdx(endpoint) => {
+                let request_url = with_params(
+                    endpoint,
+                    to_check,
+                    &[
+                        ("closest", &timestamp),
+                        ("sort", "closest"),
+                        ("filter", "statuscode:2.."),
+                        ("limit", "1"),
+                    ],
+                )?;
+                let response: CdxResponse =
+                    read_json(send_check(http_client.get(request_url)).await?).await?;
+                cdx_row(&response, endpoint, to_check)
+            }
+            FreshnessCheck::Availability(endpoint) => {
+                let request_url = with_params(endpoint, to_check, &[("timestamp", &timestamp)])?;
+                let response: AvailabilityResponse =
+                    read_json(send_check(http_client.get(request_url)).await?).await?;
+                match response.archived_snapshots.closest {
+                    Some(closest)
+                        if closest.available
+                            && closest
+                                .status
+                                .as_deref()
+                                .is_some_and(|status| status.starts_with('2')) =>
+                    {
+                        Ok(Some(Snapshot::from_wayback_timestamp(
+                            closest.url,
+                            &closest.timestamp,
+                        )?))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            FreshnessCheck::TimeGate(endpoint) => {
+                memento::timegate_memento(
+                    http_client,
+                    endpoint,
+                    to_check,
+                    Some(reference.and_utc()),
+                )
+                .await
+            }
+        }
+    }
+}
+
+impl Default for FreshnessCheck {
+    fn default() -> Self {
+        FreshnessCheck::cdx()
+    }
+}
+
+/// Sets the target as the `url` query parameter of the endpoint.
+///
+/// Any empty `url=` left at the end of the configured endpoint is replaced, so that
+/// characters such as `&`, `?` or `#` in the target are percent-encoded instead of
+/// being read as part of the query itself.
+fn with_url_param(endpoint: &str, target: &ArchivableUrl) -> Result<Url, Error> {
+    with_params(endpoint, target, &[])
+}
+
+/// Same as `with_url_param`, also setting the given parameters in place of any
+/// configured ones
+fn with_params(
+    endpoint: &str,
+    target: &ArchivableUrl,
+    params: &[(&str, &str)],
+) -> Result<Url, Error> {
+    let mut request_url =
+        Url::parse(endpoint).map_err(|e| Error::cannot_check("invalid endpoint", e))?;
+    let query: Vec<(String, String)> = request_url
+        .query_pairs()
+        .filter(|(key, _)| key != "url" && params.iter().all(|(name, _)| key != name))
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+    request_url
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(query)
+        .extend_pairs(params)
+        .append_pair("url", target.without_fragment().as_str());
+    Ok(request_url)
+}
+
+/// Sends a request to the lookup service
+pub(crate) async fn send_check(request: RequestBuilder) -> Result<Response, Error> {
+    request
+        .with_extension(Endpoint::Check)
+        .send()
+        .await
+        .map_err(|e| Error::request(Endpoint::Check, e))
+}
+
+pub(crate) fn refused(response: &Response) -> Error {
+    Error::ServiceRefused {
+        endpoint: Endpoint::Check,
+        status: response.status(),
+        retry_after: retry_after(response),
+    }
+}
+
+/// Reads the JSON body of a successful lookup response
+pub(crate) async fn read_json<T: DeserializeOwned>(response: Response) -> Result<T, Error> {
+    if !response.status().is_success() {
+        return Err(refused(&response));
+    }
+    response.json::<T>().await.map_err(|e| {
+        if e.is_decode() {
+            Error::cannot_check("invalid response", e)
+        } else {
+            Error::request(Endpoint::Check, e)
+        }
+    })
+}
+
+async fn cdx_snapshot(
+    http_client: &ClientWithMiddleware,
+    endpoint: &str,
+    to_check: &ArchivableUrl,
+) -> Result<Option<Snapshot>, Error> {
+    let response: CdxResponse =
+        read_json(send_check(http_client.get(with_url_param(endpoint, to_check)?)).await?).await?;
+    cdx_row(&response, endpoint, to_check)
+}
+
+/// Replay endpoint of the Wayback Machine serving the CDX server, found next to
+/// it: `web/` in place of `cdx/search/cdx`, e.g. `https://web.archive.org/web/`
+fn cdx_replay_endpoint(endpoint: &str) -> Result<Url, Error> {
+    let mut replay_endpoint =
+        Url::parse(endpoint).map_err(|e| Error::cannot_check("invalid endpoint", e))?;
+    let root = replay_endpoint
+        .path()
+        .strip_suffix("cdx/search/cdx")
+        .unwrap_or("/")
+        .to_string();
+    replay_endpoint.set_path(&format!("{}web/", root));
+    replay_endpoint.set_query(None);
+    Ok(replay_endpoint)
+}
+
+/// Reads the snapshot in a CDX response holding a header and a single row
+fn cdx_row(
+    response: &CdxResponse,
+    endpoint: &str,
+    to_check: &ArchivableUrl,
+) -> Result<Option<Snapshot>, Error> {
+    match &response.0[..] {
+        [_, timestamp] if timestamp.len() == 1 => {
+            let replay_url = format!(
+                "{}{}/{}",
+                cdx_replay_endpoint(endpoint)?,
+                timestamp[0],
+                to_check.without_fragment()
+            );
+            Ok(Some(Snapshot::from_wayback_timestamp(
+                replay_url,
+                &timestamp[0],
+            )?))
+        }
+        _ => Ok(None),
+    }
+}
+
+async fn availability_snapshot(
+    http_client: &ClientWithMiddleware,
+    endpoint: &str,
+    to_check: &ArchivableUrl,
+) -> Result<Option<Snapshot>, Error> {
+    let response: AvailabilityResponse =
+        read_json(send_check(http_client.get(with_url_param(endpoint, to_check)?)).await?).await?;
+
+    match response.archived_snapshots.closest {
+        Some(closest) if closest.available => Ok(Some(Snapshot::from_wayback_timestamp(
+            closest.url,
+            &closest.timestamp,
+        )?)),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replay_endpoint_next_to_cdx_server() {
+        let replay = |endpoint| cdx_replay_endpoint(endpoint).unwrap().to_string();
+        assert_eq!(
+            replay("https://web.archive.org/cdx/search/cdx"),
+            "https://web.archive.org/web/"
+        );
+        assert_eq!(
+            replay("https://mirror.example.org/wayback/cdx/search/cdx?output=json"),
+            "https://mirror.example.org/wayback/web/"
+        );
+        assert_eq!(
+            replay("https://cdx.example.org/lookup"),
+            "https://cdx.example.org/web/"
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Checks if an address is publicly routable, i.e. it is not in any of the special
+/// purpose ranges registered by IANA (private, loopback, link-local, shared, reserved,
+/// documentation, benchmarking, multicast...).
+///
+/// IPv6 addresses embedding an IPv4 address (IPv4-mapped, NAT64, 6to4) are only
+/// public if the embedded address is.
+pub fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ipv4) => is_public_ipv4(ipv4),
+        IpAddr::V6(ipv6) => is_public_ipv6(ipv6),
+    }
+}
+
+/// https://www.iana.org/assignments/iana-ipv4-special-registry/
+fn is_public_ipv4(ip: &Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(a == 0 // "this network", including 0.0.0.0
+        || ip.is_private() // 10/8, 172.16/12, 192.168/16
+        || (a == 100 && (b & 0xc0) == 64) // shared address space 100.64/10
+        || ip.is_loopback() // 127/8
+        || ip.is_link_local() // 169.254/16
+        || (a == 192 && b == 0 && c == 0) // IETF protocol assignments 192.0.0/24
+        || ip.is_documentation() // 192.0.2/24, 198.51.100/24, 203.0.113/24
+        || (a == 192 && b == 88 && c == 99) // 6to4 relay anycast 192.88.99/24
+        || (a == 198 && (b & 0xfe) == 18) // benchmarking 198.18/15
+        || ip.is_multicast() // 224/4
+        || a >= 240) // reserved 240/4, including broadcast
+}
+
+/// https://www.iana.org/assignments/iana-ipv6-special-registry/
+fn is_public_ipv6(ip: &Ipv6Addr) -> bool {
+    if let Some(ipv4) = ip.to_ipv4_mapped() {
+        // ::ffff:0:0/96
+        return is_public_ipv4(&ipv4);
+    }
+    let segments = ip.segments();
+    match segments {
+        // NAT64 64:ff9b::/96
+        [0x64, 0xff9b, 0, 0, 0, 0, high, low] => {
+            return is_public_ipv4(&ipv4_from_segments(high, low))
+        }
+        // 6to4 2002::/16
+        [0x2002, high, low, ..] => return is_public_ipv4(&ipv4_from_segments(high, low)),
+        _ => {}
+    }
+    !(segments[..6] == [0; 6] // unspecified, loopback and IPv4-compatible ::/96
+        || (segments[0] == 0x64 && segments[1] == 0xff9b && segments[2] == 1) // local-use NAT64 64:ff9b:1::/48
+        || segments[..4] == [0x100, 0, 0, 0] // discard-only 100::/64
+        || (segments[0] == 0x2001 && segments[1] == 0) // Teredo 2001::/32
+        || (segments[0] == 0x2001 && segments[1] == 2 && segments[2] == 0) // benchmarking 2001:2::/48
+        || (segments[0] == 0x2001 && (segments[1] & 0xfff0) == 0x10) // ORCHID 2001:10::/28
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8) // documentation 2001:db8::/32
+        || (segments[0] & 0xfff0) == 0x3ff0 // documentation 3fff::/20
+        || segments[0] == 0x5f00 // SRv6 SIDs 5f00::/16
+        || (segments[0] & 0xfe00) == 0xfc00 // unique local fc00::/7
+        || (segments[0] & 0xffc0) == 0xfe80 // link-local fe80::/10
+        || (segments[0] & 0xffc0) == 0xfec0 // site-local fec0::/10
+        || (segments[0] & 0xff00) == 0xff00) // multicast ff00::/8
+}
+
+fn ipv4_from_segments(high: u16, low: u16) -> Ipv4Addr {
+    Ipv4Addr::from((u32::from(high) << 16) | u32::from(low))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn public_addresses() {
+        for address in [
+            "1.1.1.1",
+            "8.8.8.8",
+            "93.184.216.34",
+            "100.63.255.255",
+            "100.128.0.0",
+            "172.32.0.1",
+            "198.20.0.1",
+            "2606:4700:4700::1111",
+            "2a00:1450:4009:81f::200e",
+            "::ffff:8.8.8.8",
+            "64:ff9b::808:808",
+            "2002:808:808::1",
+        ] {
+            assert!(is_public(&ip(address)), "{}", address);
+        }
+    }
+
+    #[test]
+    fn reserved_ipv4_addresses() {
+        for address in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "10.0.0.1",
+            "100.64.0.1",
+            "100.127.255.254",
+            "127.0.0.1",
+            "169.254.169.254",
+            "172.16.0.1",
+            "172.31.255.255",
+            "192.0.0.8",
+            "192.0.2.1",
+            "192.88.99.1",
+            "192.168.1.1",
+            "198.18.0.1",
+            "198.19.255.255",
+            "198.51.100.1",
+            "203.0.113.1",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+        ] {
+            assert!(!is_public(&ip(address)), "{}", address);
+        }
+    }
+
+    #[test]
+    fn reserved_ipv6_addresses() {
+        for address in [
+            "::",
+            "::1",
+            "::127.0.0.1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::a00:1",
+            "64:ff9b:1::1",
+            "100::1",
+            "2001::1",
+            "2001:2::1",
+            "2001:10::1",
+            "2001:db8::1",
+            "2002:7f00:1::1",
+            "3fff::1",
+            "5f00::1",
+            "fc00::1",
+            "fd12:3456:789a::1",
+            "fe80::1",
+            "fec0::1",
+            "ff02::1",
+        ] {
+            assert!(!is_public(&ip(address)), "{}", address);
+        }
+    }
+}
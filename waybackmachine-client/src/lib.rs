@@ -1,145 +1,327 @@
 pub mod archivableurl;
+pub mod backend;
+pub mod capturefailure;
+pub mod clock;
+pub mod config;
 pub mod errors;
+pub mod freshness;
+mod ipaddr;
+pub mod linkcheck;
+pub mod memento;
+mod page;
+pub mod permacc;
+pub mod policy;
+pub mod quorum;
+pub mod ratelimit;
+pub mod redirect;
+pub mod resolver;
+pub mod retry;
+pub mod rules;
+pub mod snapshot;
+pub mod soft404;
+pub mod verify;
+pub mod warc;
 
-pub use crate::archivableurl::ArchivableUrl;
-pub use crate::errors::Error;
-use chrono::{NaiveDateTime, TimeDelta, Utc};
+pub use crate::archivableurl::{ArchivableUrl, ExclusionList};
+pub use crate::backend::{ArchiveBackend, CaptureStatus, WaybackMachine};
+pub use crate::capturefailure::CaptureFailure;
+pub use crate::clock::{Clock, SystemClock};
+pub use crate::config::{ArchiveOptions, ClientConfig, ClientConfigBuilder};
+pub use crate::errors::{Error, InvalidReason, RequestError, RequestErrorKind};
+pub use crate::freshness::FreshnessCheck;
+pub use crate::linkcheck::{LinkCheck, LinkStatus};
+pub use crate::memento::MementoService;
+pub use crate::permacc::PermaCc;
+pub use crate::policy::{
+    AllowList, ArchivePolicy, Decision, DenyList, HostPolicy, Policies, SchemePolicy,
+};
+pub use crate::quorum::Quorum;
+pub use crate::ratelimit::Endpoint;
+use crate::ratelimit::RateLimitMiddleware;
+pub use crate::ratelimit::RateLimits;
+pub use crate::redirect::{RedirectChain, RedirectStrategy, TargetStatus};
+use crate::resolver::HostResolver;
+pub use crate::resolver::{Resolver, SystemResolver};
+use crate::retry::RetryMiddleware;
+pub use crate::retry::{Jitter, RetrySettings};
+pub use crate::rules::{Freshness, FreshnessRules};
+pub use crate::snapshot::Snapshot;
+pub use crate::soft404::{Soft404, Soft404Detection};
+pub use crate::verify::CaptureVerification;
+pub use crate::warc::LocalWarc;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use futures::future::join_all;
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
-use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
-use serde::Deserialize;
+use std::sync::Arc;
+use tokio::time::{sleep, Instant};
 use url::Url;
 
-/// Maximum number of allowed request retries attempts.
-const DEFAULT_MAX_REQUEST_RETRIES: u32 = 10;
-
-/// Default threshold for considering an archive as recent, in days.
-/// URLs with archives older than this threshold will be re-archived.
-const DEFAULT_ARCHIVE_THRESHOLD_DAYS: i64 = 30;
-
-/// User-agent to make requests from
-const DEFAULT_USER_AGENT: &str =
-    "Mozilla/5.0 (X11; Fedora; Linux x86_64; rv:40.0) Gecko/20100101 Firefox/40.0";
-
 /// Endpoint for the Wayback Machine archiving service
 pub const WAYBACK_MACHINE_ARCHIVE_ENDPOINT: &str = "https://web.archive.org/save/";
 /// Endpoint to check if an archive is present in the Wayback Machine
 pub const WAYBACK_MACHINE_CHECK_ENDPOINT: &str =
     "https://web.archive.org/cdx/search/cdx?fl=timestamp&limit=-1&output=json&url=";
-
-#[derive(Debug, Deserialize)]
-struct WaybackCheckResponse(Vec<Vec<String>>);
-
-/// Configuration for the Wayback Machine client
-pub struct ClientConfig {
-    archive_endpoint: String,
-    check_endpoint: String,
-    retry_policy: ExponentialBackoff,
-    archive_threshold_timestamp: NaiveDateTime,
-    user_agent: String,
-}
+/// Endpoint of the Wayback Machine Availability API
+pub const WAYBACK_MACHINE_AVAILABILITY_ENDPOINT: &str =
+    "https://archive.org/wayback/available?url=";
+/// Memento TimeGate of the Wayback Machine
+pub const WAYBACK_MACHINE_TIMEGATE_ENDPOINT: &str = "https://web.archive.org/web/";
+/// Prefix of Wayback Machine replay URLs
+pub const WAYBACK_MACHINE_REPLAY_ENDPOINT: &str = "https://web.archive.org/web/";
 
 /// Status of the archive request
+#[derive(Debug)]
 pub enum ArchiveResult {
     Archived(String),
     RecentArchiveExists,
+    /// The URL is dead: the successful snapshot closest to the reference date
+    /// is reported instead of archiving the error
+    ClosestSnapshot(Snapshot),
 }
 
-impl ClientConfig {
-    /// Constructs a new `ClientConfig` with custom retry policy and user agent
-    pub fn new(
-        archive_endpoint: String,
-        check_endpoint: String,
-        max_request_retries: u32,
-        archive_threshold_days: i64,
-        user_agent: String,
-    ) -> Self {
-        ClientConfig {
-            archive_endpoint: Url::parse(&archive_endpoint)
-                .unwrap_or_else(|_| panic!("Invalid archive_endpoint URL: {}", archive_endpoint))
-                .to_string(),
-            check_endpoint: Url::parse(&check_endpoint)
-                .unwrap_or_else(|_| panic!("Invalid check_endpoint URL: {}", check_endpoint))
-                .to_string(),
-            retry_policy: ExponentialBackoff::builder().build_with_max_retries(max_request_retries),
-            archive_threshold_timestamp: (Utc::now()
-                - TimeDelta::try_days(archive_threshold_days).unwrap())
-            .naive_utc(),
-            user_agent,
-        }
-    }
+/// Status of the archive request of a URL in one archive
+#[derive(Debug)]
+pub struct ArchiveOutcome {
+    /// Name of the archive
+    pub archive: String,
+    /// Status of the archive request, or why it failed
+    pub result: Result<ArchiveResult, Error>,
+    /// Whether a new capture holds the live page, when captures are verified
+    pub verification: Option<CaptureVerification>,
 }
-impl Default for ClientConfig {
-    /// Constructs a default `ClientConfig` with default retry policy and user agent
-    fn default() -> Self {
-        ClientConfig {
-            archive_endpoint: WAYBACK_MACHINE_ARCHIVE_ENDPOINT.into(),
-            check_endpoint: WAYBACK_MACHINE_CHECK_ENDPOINT.into(),
-            retry_policy: ExponentialBackoff::builder()
-                .build_with_max_retries(DEFAULT_MAX_REQUEST_RETRIES),
-            archive_threshold_timestamp: (Utc::now()
-                - TimeDelta::try_days(DEFAULT_ARCHIVE_THRESHOLD_DAYS).unwrap())
-            .naive_utc(),
-            user_agent: DEFAULT_USER_AGENT.into(),
-        }
-    }
+
+/// Status of the archive requests of one URL of a redirect chain
+#[derive(Debug)]
+pub struct Capture {
+    /// URL checked and archived
+    pub url: Url,
+    /// Outcome in each configured archive, in the order they were configured
+    pub outcomes: Vec<ArchiveOutcome>,
+}
+
+/// Outcome of archiving a URL
+#[derive(Debug)]
+pub struct ArchiveReport {
+    /// Redirects followed from the requested URL to its final location
+    pub redirect_chain: RedirectChain,
+    /// Status of each URL archived according to the `RedirectStrategy`:
+    /// the original URL, its final location, or both
+    pub captures: Vec<Capture>,
+    /// Why the final location likely is a missing page, when soft-404s are reported
+    pub soft_404: Option<Soft404>,
 }
 
-/// Wayback Machine client for archiving URLs
+/// Client archiving URLs in the Wayback Machine, or in other `ArchiveBackend`s
 pub struct WaybackMachineClient {
-    http_client: ClientWithMiddleware,
+    target_client: ClientWithMiddleware,
+    archives: Vec<Archive>,
     client_config: ClientConfig,
 }
 
+/// Archive along with the HTTP client its requests are sent with
+struct Archive {
+    backend: Arc<dyn ArchiveBackend>,
+    http_client: ClientWithMiddleware,
+}
+
+impl ArchiveReport {
+    /// Returns the archives that failed to preserve a URL of the chain, with their error
+    pub fn failures(&self) -> impl Iterator<Item = (&Url, &str, &Error)> {
+        self.captures.iter().flat_map(|capture| {
+            capture.outcomes.iter().filter_map(move |outcome| {
+                Some((
+                    &capture.url,
+                    outcome.archive.as_str(),
+                    outcome.result.as_ref().err()?,
+                ))
+            })
+        })
+    }
+}
+
 impl WaybackMachineClient {
     /// Constructs a new `WaybackMachineClient` with the given configuration
     pub fn new(client_config: ClientConfig) -> Self {
-        let http_client = ClientBuilder::new(
-            reqwest::Client::builder()
-                .user_agent(client_config.user_agent.clone())
+        let reqwest_client = || {
+            let mut reqwest_client =
+                reqwest::Client::builder().user_agent(client_config.user_agent.clone());
+            if let Some(timeout) = client_config.timeout {
+                reqwest_client = reqwest_client.timeout(timeout);
+            }
+            if let Some(connect_timeout) = client_config.connect_timeout {
+                reqwest_client = reqwest_client.connect_timeout(connect_timeout);
+            }
+            if let Some(proxy) = client_config.proxy.clone() {
+                reqwest_client = reqwest_client.proxy(proxy);
+            }
+            // host names are checked when connecting, whether to the URLs to archive,
+            // to the locations they redirect to or to the archives
+            let host_resolver = match client_config.resolver.clone() {
+                Some(resolver) => HostResolver::public_only(resolver),
+                None => HostResolver::system(),
+            };
+            reqwest_client.dns_resolver(Arc::new(host_resolver))
+        };
+        let retry_middleware = || {
+            RetryMiddleware::new(
+                &client_config.target_retry,
+                &client_config.check_retry,
+                &client_config.save_retry,
+            )
+        };
+        // redirects of the URLs to archive are followed one by one, to validate each hop
+        let target_client = ClientBuilder::new(
+            reqwest_client()
+                .redirect(reqwest::redirect::Policy::none())
                 .build()
                 .unwrap(),
         )
-        .with(RetryTransientMiddleware::new_with_policy(
-            client_config.retry_policy,
-        ))
+        .with(retry_middleware())
         .build();
+        let mut backends = client_config.backends.clone();
+        if backends.is_empty() {
+            backends.push(Arc::new(WaybackMachine::from_config(&client_config)));
+        }
+        // each archive is rate limited on its own, so that one throttling or
+        // failing archive does not hold back the others
+        let archives = backends
+            .into_iter()
+            .map(|backend| {
+                let mut reqwest_client = reqwest_client();
+                if backend.fetches_targets() {
+                    reqwest_client = reqwest_client.redirect(reqwest::redirect::Policy::none());
+                }
+                Archive {
+                    backend,
+                    http_client: ClientBuilder::new(reqwest_client.build().unwrap())
+                        .with(retry_middleware())
+                        .with(RateLimitMiddleware::new(&client_config.rate_limits))
+                        .build(),
+                }
+            })
+            .collect();
         WaybackMachineClient {
-            http_client,
+            target_client,
+            archives,
             client_config,
         }
     }
 
-    /// Checks if a recent archive exists for the given URL.
-    ///
-    /// If an archive exists, and it is newer than the configured archive threshold,
-    /// the function returns Ok(()), indicating that the URL is considered recently archived.
-    /// If no recent archive is found or the found archive is older than the threshold,
-    /// it returns Err(Error::NoRecentArchive).
-    ///
-    /// https://github.com/internetarchive/wayback/tree/master/wayback-cdx-server
+    /// Parses and validates the URL using the configured `Policies`
+    fn parse_url(&self, url: &str) -> Result<ArchivableUrl, Error> {
+        ArchivableUrl::parse_with_policy(url, self.client_config.policy.as_ref())
+    }
+
+    /// Looks up the most recent snapshot of the given URL in the first configured
+    /// `ArchiveBackend`, by default the Wayback Machine using its `FreshnessCheck` service.
     ///
-    async fn check_recent_archive_exists(&self, url: &str) -> Result<(), Error> {
-        let to_check = ArchivableUrl::parse(url)?;
-        let response = self
-            .http_client
-            .get(format!("{}{}", self.client_config.check_endpoint, to_check))
-            .send()
+    /// Returns `Ok(None)` if the URL has never been archived.
+    pub async fn latest_snapshot(&self, url: &str) -> Result<Option<Snapshot>, Error> {
+        let to_check = self.parse_url(url)?;
+        self.archives[0].latest_snapshot(&to_check).await
+    }
+
+    /// Lists the mementos of the given URL in a Memento-compliant archive or
+    /// aggregator, oldest first, using its link-format TimeMap.
+    pub async fn mementos(
+        &self,
+        service: &MementoService,
+        url: &str,
+    ) -> Result<Vec<Snapshot>, Error> {
+        let to_check = self.parse_url(url)?;
+        service
+            .timemap(&self.archives[0].http_client, &to_check)
             .await
-            .map_err(|err| Error::CannotCheckArchive(err.to_string()))?
-            .json::<WaybackCheckResponse>()
+    }
+
+    /// Looks up the memento of the given URL closest to `datetime` through the
+    /// TimeGate of a Memento-compliant archive or aggregator, or the latest one
+    /// without a datetime.
+    ///
+    /// Returns `Ok(None)` if the URL has never been archived.
+    pub async fn memento_at(
+        &self,
+        service: &MementoService,
+        url: &str,
+        datetime: Option<DateTime<Utc>>,
+    ) -> Result<Option<Snapshot>, Error> {
+        let to_check = self.parse_url(url)?;
+        service
+            .memento_at(&self.archives[0].http_client, &to_check, datetime)
             .await
-            .map_err(|e| Error::CannotCheckArchive(e.to_string()))?;
-
-        match &response.0[..] {
-            [_, timestamp] if timestamp.len() == 1 => {
-                let snapshot_timestamp =
-                    NaiveDateTime::parse_from_str(&timestamp[0], "%Y%m%d%H%M%S")?;
-                if snapshot_timestamp > self.client_config.archive_threshold_timestamp {
-                    Ok(())
-                } else {
-                    Err(Error::NoRecentArchive(url.to_string()))
-                }
+    }
+
+    /// Returns the freshness applying to the given URL: the per-request threshold
+    /// if any, otherwise the first matching freshness rule, otherwise the configured
+    /// archive threshold.
+    pub fn freshness(&self, url: &Url, options: &ArchiveOptions) -> Freshness {
+        let default_freshness = match (self.client_config.reference_window, options.reference_date)
+        {
+            (Some(reference_window), Some(_)) => Freshness::NearReference(reference_window),
+            _ => Freshness::MaxAge(self.client_config.archive_threshold),
+        };
+        match options.archive_threshold {
+            Some(archive_threshold) => Freshness::MaxAge(archive_threshold),
+            None => self
+                .client_config
+                .freshness_rules
+                .lookup(url)
+                .unwrap_or(default_freshness),
+        }
+    }
+
+    /// Checks whether a snapshot of the given URL is recent enough.
+    ///
+    /// The threshold is evaluated against the configured clock on every call,
+    /// so that a long-lived client does not drift.
+    pub fn is_recent(&self, url: &Url, snapshot: &Snapshot, options: &ArchiveOptions) -> bool {
+        let max_age = match (self.freshness(url, options), options.reference_date) {
+            (Freshness::AnySnapshot, _) => return true,
+            (Freshness::NearReference(window), Some(reference)) => {
+                return snapshot.timestamp.signed_duration_since(reference).abs() <= window;
             }
+            // without a reference date, the age is counted from now
+            (Freshness::MaxAge(max_age) | Freshness::NearReference(max_age), _) => max_age,
+        };
+        self.client_config
+            .clock
+            .now()
+            .naive_utc()
+            .checked_sub_signed(max_age)
+            .is_none_or(|threshold| snapshot.timestamp > threshold)
+    }
+
+    /// Checks if a recent archive exists for the given URL.
+    ///
+    /// If an archive exists, and it is newer than the archive threshold,
+    /// the function returns it, indicating that the URL is considered recently archived.
+    /// If no recent archive is found or the found archive is older than the threshold,
+    /// it returns Err(Error::NoRecentArchive).
+    async fn check_recent_archive_exists_in(
+        &self,
+        archive: &Archive,
+        url: &str,
+        options: &ArchiveOptions,
+    ) -> Result<Snapshot, Error> {
+        let to_check = self.parse_url(url)?;
+        let snapshot = match (
+            self.freshness(&to_check.url, options),
+            options.reference_date,
+        ) {
+            // a later snapshot must not hide one taken near the reference date
+            (Freshness::NearReference(_), Some(reference)) => match archive
+                .backend
+                .closest_snapshot(&archive.http_client, &to_check, reference)
+                .await?
+            {
+                Some(snapshot) => Some(snapshot),
+                // archives unable to search by date only have their latest snapshot
+                None => archive.latest_snapshot(&to_check).await?,
+            },
+            _ => archive.latest_snapshot(&to_check).await?,
+        };
+        match snapshot {
+            Some(snapshot) if self.is_recent(&to_check.url, &snapshot, options) => Ok(snapshot),
             _ => Err(Error::NoRecentArchive(url.to_string())),
         }
     }
@@ -147,17 +329,24 @@ impl WaybackMachineClient {
     /// Checks if a recent Wayback Machine archive exists for the given URL
     /// and archives it if necessary.
     ///
-    /// This function first checks if a recent archive exists for the URL by calling
+    /// This function first follows the redirects of the URL with `resolve_redirects`.
+    /// Then, for the original URL, its final location or both, depending on the
+    /// configured `RedirectStrategy`, it checks if a recent archive exists by calling
     /// `check_recent_archive_exists`. If an archive does not exist or is older than the
-    /// configured archive threshold, it proceeds to archive the URL.
+    /// configured archive threshold, it proceeds to archive that same URL.
     ///
-    /// It returns an `ArchiveResult::Archived` if it archives the URL,
-    /// or an `ArchiveResult::RecentArchiveExists` if a recent archive already exists.
+    /// Every configured archive is checked and asked to archive in parallel. The
+    /// returned `ArchiveReport` holds the redirect chain and, for each URL and
+    /// archive, an `ArchiveResult::Archived` if it archives the URL, an
+    /// `ArchiveResult::RecentArchiveExists` if a recent archive already exists,
+    /// or the error it failed with.
     ///
     /// # Errors
     ///
-    /// This method fails if the `url` provided is not well formatted
-    /// of if there was an error while sending the request.
+    /// This method fails if the `url` provided is not well formatted, or if fewer
+    /// archives than the configured `Quorum` preserved it. With a single archive,
+    /// its error is returned as is, otherwise an `Error::QuorumNotReached` holding
+    /// the whole report, with the outcome in each archive of every URL.
     ///
     /// # Example
     /// ```
@@ -169,70 +358,358 @@ impl WaybackMachineClient {
     /// # Ok(())
     /// # }
     /// ```
-    pub async fn archive_url(&self, url: &str) -> Result<ArchiveResult, Error> {
-        let to_archive = ArchivableUrl::parse(url)?;
-        // get the latest location in case of a redirect
-        // check that the latest location is actually archivable
-        let to_check = self
-            .http_client
-            .get(to_archive.as_str())
-            .send()
+    pub async fn archive_url(&self, url: &str) -> Result<ArchiveReport, Error> {
+        self.archive_url_with_options(url, &ArchiveOptions::default())
             .await
-            .map_or(Ok(to_archive.clone()), |response| {
-                ArchivableUrl::parse(response.url().as_str())
-            })?
-            .url
-            .clone();
+    }
+
+    /// Follows the redirects of the URL, validating each location before requesting it.
+    ///
+    /// At most `max_redirects` redirects are followed, and redirect loops are cut
+    /// short. A location refused by the policy fails the resolution.
+    pub async fn resolve_redirects(&self, url: &str) -> Result<RedirectChain, Error> {
+        let to_resolve = self.parse_url(url)?;
+        let mut redirect_chain = RedirectChain::new(to_resolve.url);
+        while redirect_chain.hops() < self.client_config.max_redirects {
+            let hop = redirect::visit(&self.target_client, redirect_chain.final_url()).await?;
+            redirect_chain.set_status(hop.status);
+            redirect_chain.set_content_type(hop.content_type);
+            let Some(location) = hop.location else {
+                break;
+            };
+            if redirect_chain.contains(&location) {
+                break;
+            }
+            let location = self.parse_url(location.as_str())?;
+            redirect_chain.push(location.url);
+        }
+        Ok(redirect_chain)
+    }
+
+    /// Checks the health of a link without archiving it: follows its redirects,
+    /// classifies how its final location answers, and looks up the successful
+    /// snapshot of the URL closest to the reference date (now by default) in the
+    /// first configured archive.
+    pub async fn check_url(&self, url: &str, options: &ArchiveOptions) -> Result<LinkCheck, Error> {
+        let redirect_chain = self.resolve_redirects(url).await?;
+        let to_check = ArchivableUrl {
+            url: redirect_chain.original().clone(),
+        };
+        let reference = options
+            .reference_date
+            .unwrap_or_else(|| self.client_config.clock.now().naive_utc());
+        let archive = &self.archives[0];
+        let snapshot = archive
+            .backend
+            .closest_snapshot(&archive.http_client, &to_check, reference)
+            .await;
+        Ok(LinkCheck {
+            status: LinkStatus::of(&redirect_chain),
+            redirect_chain,
+            snapshot,
+        })
+    }
 
+    /// Same as `archive_url`, with options overriding the client configuration
+    /// for this request only.
+    pub async fn archive_url_with_options(
+        &self,
+        url: &str,
+        options: &ArchiveOptions,
+    ) -> Result<ArchiveReport, Error> {
+        // get the latest location in case of a redirect
+        let redirect_chain = self.resolve_redirects(url).await?;
+        let redirect_strategy = options
+            .redirect_strategy
+            .unwrap_or(self.client_config.redirect_strategy);
+        let dead = self.client_config.closest_for_dead_links && redirect_chain.is_dead();
+        let soft_404 = match self.client_config.soft_404_detection {
+            Soft404Detection::Off => None,
+            _ if redirect_chain.is_dead() => None,
+            _ => soft404::detect(&self.target_client, &redirect_chain).await,
+        };
+        if let (Soft404Detection::Skip, Some(soft_404)) =
+            (self.client_config.soft_404_detection, &soft_404)
+        {
+            return Err(Error::LikelySoft404 {
+                url: redirect_chain.original().to_string(),
+                soft_404: soft_404.clone(),
+            });
+        }
+        let mut captures = Vec::new();
+        // the first URL preserved in too few archives, once every URL was tried
+        let mut missed = None;
+        for to_archive in redirect_strategy.urls(&redirect_chain) {
+            let to_archive = ArchivableUrl {
+                url: to_archive.clone(),
+            };
+            let (outcomes, reached) = if dead {
+                // a single snapshot is enough, as nothing is left to capture
+                let reference = options
+                    .reference_date
+                    .unwrap_or_else(|| self.client_config.clock.now().naive_utc());
+                let results = join_all(self.archives.iter().map(|archive| {
+                    self.closest_snapshot_in(archive, &to_archive, &redirect_chain, reference)
+                }))
+                .await;
+                self.check_quorum(results, 1)?
+            } else {
+                let results = join_all(
+                    self.archives
+                        .iter()
+                        .map(|archive| self.archive_resolved_url(archive, &to_archive, options)),
+                )
+                .await;
+                let required = self.client_config.quorum.required(self.archives.len());
+                let (mut outcomes, reached) = self.check_quorum(results, required)?;
+                if self.client_config.verify_captures {
+                    self.verify_captures(&mut outcomes, redirect_chain.final_url())
+                        .await;
+                }
+                (outcomes, reached)
+            };
+            if !reached && missed.is_none() {
+                let required = if dead {
+                    1
+                } else {
+                    self.client_config.quorum.required(self.archives.len())
+                };
+                missed = Some((to_archive.url.to_string(), required));
+            }
+            captures.push(Capture {
+                url: to_archive.url,
+                outcomes,
+            });
+        }
+        let report = ArchiveReport {
+            redirect_chain,
+            captures,
+            soft_404,
+        };
+        match missed {
+            Some((url, required)) => Err(Error::QuorumNotReached {
+                url,
+                required,
+                report: Box::new(report),
+            }),
+            None => Ok(report),
+        }
+    }
+
+    /// Pairs each result with its archive, telling whether at least `required`
+    /// succeeded. With a single archive, its error is returned as is.
+    fn check_quorum(
+        &self,
+        mut results: Vec<Result<ArchiveResult, Error>>,
+        required: usize,
+    ) -> Result<(Vec<ArchiveOutcome>, bool), Error> {
+        let succeeded = results.iter().filter(|result| result.is_ok()).count();
+        if succeeded < required && results.len() == 1 {
+            if let Some(Err(err)) = results.pop() {
+                return Err(err);
+            }
+        }
+        let outcomes = self
+            .archives
+            .iter()
+            .zip(results)
+            .map(|(archive, result)| ArchiveOutcome {
+                archive: archive.backend.name().to_string(),
+                result,
+                verification: None,
+            })
+            .collect();
+        Ok((outcomes, succeeded >= required))
+    }
+
+    /// Compares the new captures of a URL, replayed as they were archived, with
+    /// the live page at its final location
+    async fn verify_captures(&self, outcomes: &mut [ArchiveOutcome], live_url: &Url) {
+        if !outcomes
+            .iter()
+            .any(|outcome| matches!(outcome.result, Ok(ArchiveResult::Archived(_))))
+        {
+            return;
+        }
+        let live = page::fetch(&self.target_client, live_url, Endpoint::Target).await;
+        let verifications = join_all(self.archives.iter().zip(outcomes.iter()).map(
+            |(archive, outcome)| {
+                let live = &live;
+                async move {
+                    let Ok(ArchiveResult::Archived(archive_url)) = &outcome.result else {
+                        return None;
+                    };
+                    let Some(raw_replay_url) = archive.backend.raw_replay_url(archive_url) else {
+                        return Some(CaptureVerification::Unverified("no raw replay".to_string()));
+                    };
+                    Some(match live {
+                        Ok(live) if live.status.is_success() => {
+                            verify::verify(&archive.http_client, &raw_replay_url, live).await
+                        }
+                        Ok(live) => CaptureVerification::Unverified(format!(
+                            "live page answered {}",
+                            live.status
+                        )),
+                        Err(e) => {
+                            CaptureVerification::Unverified(format!("live page failed: {}", e))
+                        }
+                    })
+                }
+            },
+        ))
+        .await;
+        for (outcome, verification) in outcomes.iter_mut().zip(verifications) {
+            outcome.verification = verification;
+        }
+    }
+
+    /// Finds the successful snapshot of a dead URL closest to the reference date
+    async fn closest_snapshot_in(
+        &self,
+        archive: &Archive,
+        to_check: &ArchivableUrl,
+        redirect_chain: &RedirectChain,
+        reference: NaiveDateTime,
+    ) -> Result<ArchiveResult, Error> {
+        match archive
+            .backend
+            .closest_snapshot(&archive.http_client, to_check, reference)
+            .await?
+        {
+            Some(snapshot) => Ok(ArchiveResult::ClosestSnapshot(snapshot)),
+            None => Err(Error::DeadUrl {
+                url: to_check.url.to_string(),
+                status: redirect_chain.target_status(),
+            }),
+        }
+    }
+
+    /// Archives a validated URL unless it has a recent snapshot, checking and
+    /// saving the very same URL
+    async fn archive_resolved_url(
+        &self,
+        archive: &Archive,
+        to_archive: &ArchivableUrl,
+        options: &ArchiveOptions,
+    ) -> Result<ArchiveResult, Error> {
+        let url = to_archive.url.as_str();
         if self
-            .check_recent_archive_exists(to_check.as_str())
+            .check_recent_archive_exists_in(archive, url, options)
             .await
             .is_ok()
         {
             return Ok(ArchiveResult::RecentArchiveExists);
         }
 
-        let response = self
-            .http_client
-            .get(format!(
-                "{}{}",
-                self.client_config.archive_endpoint, to_archive
-            ))
-            .send()
-            .await?;
-        if !response.status().is_success() {
+        match self.capture(archive, to_archive).await {
+            Ok(archive_url) => Ok(ArchiveResult::Archived(archive_url)),
             // check just in case the request returns a false negative
-            if self.check_recent_archive_exists(url).await.is_err() {
-                return Err(Error::CannotArchive(
-                    response.status().to_string(),
-                    url.to_string(),
-                ));
+            Err(err @ Error::CannotArchive { .. }) => {
+                match self
+                    .check_recent_archive_exists_in(archive, url, options)
+                    .await
+                {
+                    Ok(snapshot) => Ok(ArchiveResult::Archived(snapshot.url)),
+                    Err(_) => Err(err),
+                }
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Requests a capture of the URL, and polls it until it completes
+    async fn capture(
+        &self,
+        archive: &Archive,
+        to_archive: &ArchivableUrl,
+    ) -> Result<String, Error> {
+        let deadline = Instant::now() + self.client_config.capture_timeout;
+        let mut capture_status = archive
+            .backend
+            .request_capture(&archive.http_client, to_archive)
+            .await?;
+        loop {
+            match capture_status {
+                CaptureStatus::Done(archive_url) => return Ok(archive_url),
+                CaptureStatus::Pending(job_id) => {
+                    if Instant::now() + self.client_config.capture_poll_interval > deadline {
+                        return Err(Error::CapturePending {
+                            url: to_archive.url.to_string(),
+                            job_id,
+                        });
+                    }
+                    sleep(self.client_config.capture_poll_interval).await;
+                    capture_status = archive
+                        .backend
+                        .capture_status(&archive.http_client, to_archive, &job_id)
+                        .await?;
+                }
             }
         }
-        Ok(ArchiveResult::Archived(response.url().to_string()))
+    }
+}
+
+impl Archive {
+    async fn latest_snapshot(&self, to_check: &ArchivableUrl) -> Result<Option<Snapshot>, Error> {
+        self.backend
+            .latest_snapshot(&self.http_client, to_check)
+            .await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use mockito::ServerGuard;
+    use crate::resolver::tests::StaticResolver;
+    use chrono::{NaiveDateTime, TimeDelta, Utc};
+    use mockito::{Matcher, ServerGuard};
+    use reqwest::StatusCode;
     use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    impl WaybackMachineClient {
+        /// Checks if a recent archive exists for the given URL in the first archive
+        async fn check_recent_archive_exists(
+            &self,
+            url: &str,
+            options: &ArchiveOptions,
+        ) -> Result<Snapshot, Error> {
+            self.check_recent_archive_exists_in(&self.archives[0], url, options)
+                .await
+        }
+    }
 
     const ARCHIVE_ROOT_PATH: &str = "/save/";
+    const CHECK_PATH: &str = "/cdx/search/cdx";
     const CHECK_ROOT_PATH: &str = "/cdx/search/cdx?fl=timestamp&limit=-1&output=json&url=";
+    const AVAILABILITY_PATH: &str = "/wayback/available";
+    const AVAILABILITY_ROOT_PATH: &str = "/wayback/available?url=";
+    const TIMEGATE_ROOT_PATH: &str = "/web/";
     const MAX_REQUEST_RETRIES: u32 = 3;
 
+    fn check_query(url: &str) -> Matcher {
+        Matcher::AllOf(vec![
+            Matcher::UrlEncoded("fl".into(), "timestamp".into()),
+            Matcher::UrlEncoded("limit".into(), "-1".into()),
+            Matcher::UrlEncoded("output".into(), "json".into()),
+            Matcher::UrlEncoded("url".into(), url.into()),
+        ])
+    }
+
+    fn mock_config(server: &ServerGuard) -> ClientConfigBuilder {
+        ClientConfig::builder()
+            .archive_endpoint(format!("{}{}", server.url(), ARCHIVE_ROOT_PATH))
+            .check_endpoint(format!("{}{}", server.url(), CHECK_ROOT_PATH))
+            .max_request_retries(MAX_REQUEST_RETRIES)
+            .archive_threshold_days(30)
+            .user_agent("TestUserAgent")
+            .rate_limits(RateLimits::unlimited())
+    }
+
     async fn mock_server() -> (ServerGuard, WaybackMachineClient) {
         let server = mockito::Server::new_async().await;
-        let client_config = ClientConfig::new(
-            format!("{}{}", server.url(), ARCHIVE_ROOT_PATH),
-            format!("{}{}", server.url(), CHECK_ROOT_PATH),
-            MAX_REQUEST_RETRIES,
-            30,
-            "TestUserAgent".to_string(),
-        );
-        let wayback_client = WaybackMachineClient::new(client_config);
+        let wayback_client = WaybackMachineClient::new(mock_config(&server).build().unwrap());
         (server, wayback_client)
     }
 
@@ -254,7 +731,8 @@ mod tests {
             }
         });
         let mock1 = server
-            .mock("GET", &format!("{}{}", CHECK_ROOT_PATH, to_archive)[..])
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
             .with_status(200)
             .with_body(snapshot.to_string())
             .create_async()
@@ -270,15 +748,124 @@ mod tests {
         mock2.assert_async().await;
     }
 
+    #[tokio::test]
+    async fn test_archive_url_with_credentials() {
+        let to_archive = "https://example.com/";
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .credentials("access", "secret")
+                .build()
+                .unwrap(),
+        );
+
+        let mock1 = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .match_header("authorization", Matcher::Missing)
+            .with_status(200)
+            .with_body(json!([]).to_string())
+            .create_async()
+            .await;
+        let mock2 = server
+            .mock("GET", &format!("{}{}", ARCHIVE_ROOT_PATH, to_archive)[..])
+            .match_header("authorization", "LOW access:secret")
+            .with_status(200)
+            .create_async()
+            .await;
+
+        assert!(wayback_client.archive_url(to_archive).await.is_ok());
+        mock1.assert_async().await;
+        mock2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_with_query_and_fragment() {
+        let to_archive = "https://example.com/search?q=a%20b&page=2#results";
+        let (mut server, wayback_client) = mock_server().await;
+
+        let mock1 = server
+            .mock("GET", CHECK_PATH)
+            .match_query(Matcher::Exact(
+                "fl=timestamp&limit=-1&output=json\
+                 &url=https%3A%2F%2Fexample.com%2Fsearch%3Fq%3Da%2520b%26page%3D2"
+                    .to_string(),
+            ))
+            .with_status(200)
+            .with_body(json!([]).to_string())
+            .create_async()
+            .await;
+        let mock2 = server
+            .mock(
+                "GET",
+                &format!(
+                    "{}{}",
+                    ARCHIVE_ROOT_PATH, "https://example.com/search?q=a%20b&page=2"
+                )[..],
+            )
+            .with_status(200)
+            .create_async()
+            .await;
+
+        assert!(wayback_client.archive_url(to_archive).await.is_ok());
+        mock1.assert_async().await;
+        mock2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_recent_archive_exists_encodes_ampersand() {
+        let to_archive = "https://example.com/?a=1&limit=5";
+        let snapshot_timestamp = (Utc::now() - TimeDelta::try_days(1).unwrap())
+            .format("%Y%m%d%H%M%S")
+            .to_string();
+        let (mut server, wayback_client) = mock_server().await;
+
+        let snapshot: Value = json!([["timestamp"], [snapshot_timestamp]]);
+        let mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .create_async()
+            .await;
+
+        assert!(wayback_client
+            .check_recent_archive_exists(to_archive, &ArchiveOptions::default())
+            .await
+            .is_ok());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_custom_exclusions() {
+        let mut exclusions = ExclusionList::empty();
+        exclusions.add("example.com");
+        let wayback_client = WaybackMachineClient::new(
+            ClientConfig::builder()
+                .policy(Policies::new().with(exclusions))
+                .build()
+                .unwrap(),
+        );
+
+        assert!(matches!(
+            wayback_client.archive_url("https://www.example.com/").await.err().unwrap(),
+            Error::ExcludedUrl(u, r)
+                if u == "https://www.example.com/"
+                    && r == InvalidReason::ExcludedDomain { rule: "example.com".into() }
+        ));
+    }
+
     #[tokio::test]
     async fn test_archive_url_no_scheme() {
         let to_archive = "example.com";
         let wayback_client = WaybackMachineClient::new(ClientConfig::default());
 
-        assert_eq!(
+        assert!(matches!(
             wayback_client.archive_url(to_archive).await.err().unwrap(),
-            Error::InvalidUrl(to_archive.to_string())
-        );
+            Error::InvalidUrl(u, r)
+                if u == to_archive
+                    && r == InvalidReason::Unparseable(url::ParseError::RelativeUrlWithoutBase)
+        ));
     }
 
     #[tokio::test]
@@ -286,10 +873,66 @@ mod tests {
         let to_archive = "http://localhost/page";
         let wayback_client = WaybackMachineClient::new(ClientConfig::default());
 
-        assert_eq!(
+        assert!(matches!(
+            wayback_client.archive_url(to_archive).await.err().unwrap(),
+            Error::InvalidUrl(u, r)
+                if u == to_archive && r == InvalidReason::Localhost
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_resolving_to_private_address() {
+        let to_archive = "https://intranet.example.com/page";
+        let server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .resolver(StaticResolver::new(&[(
+                    "intranet.example.com",
+                    "192.168.0.10",
+                )]))
+                .build()
+                .unwrap(),
+        );
+
+        // rejected when connecting, so no mock is needed
+        assert!(matches!(
             wayback_client.archive_url(to_archive).await.err().unwrap(),
-            Error::InvalidUrl(to_archive.to_string())
+            Error::InvalidUrl(u, r)
+                if u == to_archive
+                    && r == InvalidReason::ResolvesToPrivateAddress("192.168.0.10".parse().unwrap())
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_redirects_to_private_address() {
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .policy(SchemePolicy::default())
+                .resolver(StaticResolver::new(&[("rebound.example.com", "127.0.0.1")]))
+                .build()
+                .unwrap(),
         );
+        let port = server.socket_address().port();
+        let location = format!("http://rebound.example.com:{}/b", port);
+        server
+            .mock("HEAD", "/a")
+            .with_status(302)
+            .with_header("Location", &location)
+            .create_async()
+            .await;
+        let private_mock = server.mock("HEAD", "/b").expect(0).create_async().await;
+
+        // the host of each location is resolved by the client connecting to it
+        assert!(matches!(
+            wayback_client
+                .resolve_redirects(&format!("{}/a", server.url()))
+                .await,
+            Err(Error::InvalidUrl(u, r))
+                if u == location
+                    && r == InvalidReason::ResolvesToPrivateAddress("127.0.0.1".parse().unwrap())
+        ));
+        private_mock.assert_async().await;
     }
 
     #[tokio::test]
@@ -310,7 +953,8 @@ mod tests {
             }
         });
         let mock1 = server
-            .mock("GET", &format!("{}{}", CHECK_ROOT_PATH, to_archive)[..])
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
             .with_status(200)
             .with_body(snapshot.to_string())
             .create_async()
@@ -323,81 +967,1315 @@ mod tests {
             .await;
         // checking if it actually was archived after receiving an archiving error
         let mock3 = server
-            .mock("GET", &format!("{}{}", CHECK_ROOT_PATH, to_archive)[..])
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
             .with_status(200)
             .with_body(snapshot.to_string())
             .create_async()
             .await;
 
-        assert!(wayback_client.archive_url(to_archive).await.is_err());
+        let err = wayback_client.archive_url(to_archive).await.err().unwrap();
+        assert!(matches!(err, Error::CannotArchive { .. }));
+        assert_eq!(err.status().map(|status| status.as_u16()), Some(520));
         mock1.assert_async().await;
         mock2.assert_async().await;
         mock3.assert_async().await;
     }
 
     #[tokio::test]
-    async fn test_check_recent_archive_exists_success() {
+    async fn test_archive_url_json_error() {
         let to_archive = "https://example.com/";
-        let snapshot_timestamp = (Utc::now() - TimeDelta::try_days(1).unwrap())
-            .format("%Y%m%d%H%M%S")
-            .to_string();
         let (mut server, wayback_client) = mock_server().await;
 
-        let snapshot: Value = json!([["timestamp"], [snapshot_timestamp]]);
-        let mock = server
-            .mock("GET", &format!("{}{}", CHECK_ROOT_PATH, to_archive)[..])
+        server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
             .with_status(200)
-            .with_body(snapshot.to_string())
+            .with_body("[]")
+            .expect(2)
+            .create_async()
+            .await;
+        // Save Page Now answers errors of its JSON API with a 200
+        server
+            .mock("GET", &format!("{}{}", ARCHIVE_ROOT_PATH, to_archive)[..])
+            .with_status(200)
+            .with_body(r#"{"status": "error", "status_ext": "error:too-many-daily-captures", "message": "This URL has been already captured 10 times today."}"#)
             .create_async()
             .await;
 
-        assert!(wayback_client
-            .check_recent_archive_exists(to_archive)
-            .await
-            .is_ok());
-        mock.assert_async().await;
+        let err = wayback_client.archive_url(to_archive).await.err().unwrap();
+        assert!(matches!(
+            err,
+            Error::CannotArchive {
+                cause: CaptureFailure::DailyLimitReached,
+                ..
+            }
+        ));
     }
 
     #[tokio::test]
-    async fn test_check_recent_archive_exists_old_snapshot() {
+    async fn test_archive_url_excluded_by_site_owner() {
         let to_archive = "https://example.com/";
-        let snapshot_timestamp = (Utc::now() - TimeDelta::try_days(100).unwrap())
-            .format("%Y%m%d%H%M%S")
-            .to_string();
         let (mut server, wayback_client) = mock_server().await;
 
-        let snapshot: Value = json!([["timestamp"], [snapshot_timestamp]]);
-        let mock = server
-            .mock("GET", &format!("{}{}", CHECK_ROOT_PATH, to_archive)[..])
+        let check_mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
             .with_status(200)
-            .with_body(snapshot.to_string())
+            .with_body("[]")
+            .expect(2)
+            .create_async()
+            .await;
+        let save_mock = server
+            .mock("GET", &format!("{}{}", ARCHIVE_ROOT_PATH, to_archive)[..])
+            .with_status(403)
+            .with_body("<p>Sorry.</p><p>This URL has been excluded from the Wayback Machine.</p>")
             .create_async()
             .await;
 
-        assert!(wayback_client
-            .check_recent_archive_exists(to_archive)
-            .await
-            .is_err());
-        mock.assert_async().await;
+        let err = wayback_client.archive_url(to_archive).await.err().unwrap();
+        assert!(matches!(
+            err,
+            Error::CannotArchive {
+                cause: CaptureFailure::ExcludedBySiteOwner,
+                ..
+            }
+        ));
+        check_mock.assert_async().await;
+        save_mock.assert_async().await;
     }
 
     #[tokio::test]
-    async fn test_check_recent_archive_exists_no_snapshot() {
+    async fn test_archive_url_retryable_statuses() {
         let to_archive = "https://example.com/";
-        let (mut server, wayback_client) = mock_server().await;
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .save_retry(RetrySettings {
+                    max_retries: MAX_REQUEST_RETRIES,
+                    min_interval: Duration::from_millis(1),
+                    max_interval: Duration::from_millis(10),
+                    retryable_statuses: Some(vec![StatusCode::from_u16(523).unwrap()]),
+                    ..Default::default()
+                })
+                .build()
+                .unwrap(),
+        );
 
-        let snapshot: Value = json!([]);
-        let mock = server
-            .mock("GET", &format!("{}{}", CHECK_ROOT_PATH, to_archive)[..])
+        let check_mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
             .with_status(200)
-            .with_body(snapshot.to_string())
+            .with_body("[]")
+            .expect(2)
+            .create_async()
+            .await;
+        // not in the retryable statuses, so only requested once
+        let save_mock = server
+            .mock("GET", &format!("{}{}", ARCHIVE_ROOT_PATH, to_archive)[..])
+            .with_status(520)
+            .expect(1)
+            .create_async()
+            .await;
+        assert!(wayback_client.archive_url(to_archive).await.is_err());
+        check_mock.assert_async().await;
+        save_mock.assert_async().await;
+
+        // retried until the maximum number of retries
+        let save_mock = server
+            .mock("GET", &format!("{}{}", ARCHIVE_ROOT_PATH, to_archive)[..])
+            .with_status(523)
+            .expect(MAX_REQUEST_RETRIES as usize + 1)
             .create_async()
             .await;
+        assert!(wayback_client.archive_url(to_archive).await.is_err());
+        save_mock.assert_async().await;
+    }
 
-        assert!(wayback_client
-            .check_recent_archive_exists(to_archive)
-            .await
-            .is_err());
-        mock.assert_async().await;
+    /// Client allowing loopback targets, so that the mock server can redirect
+    async fn mock_redirect_server(max_redirects: usize) -> (ServerGuard, WaybackMachineClient) {
+        let server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .policy(SchemePolicy::default())
+                .max_redirects(max_redirects)
+                .build()
+                .unwrap(),
+        );
+        (server, wayback_client)
+    }
+
+    #[tokio::test]
+    async fn test_resolve_redirects() {
+        let (mut server, wayback_client) = mock_redirect_server(10).await;
+        let to_archive = format!("{}/a", server.url());
+
+        let mock_a = server
+            .mock("HEAD", "/a")
+            .with_status(301)
+            .with_header("Location", "/b")
+            .create_async()
+            .await;
+        // servers refusing HEAD requests are asked with a GET
+        let mock_b_head = server
+            .mock("HEAD", "/b")
+            .with_status(405)
+            .create_async()
+            .await;
+        let mock_b_get = server
+            .mock("GET", "/b")
+            .with_status(302)
+            .with_header("Location", &format!("{}/c", server.url()))
+            .create_async()
+            .await;
+        let mock_c = server
+            .mock("HEAD", "/c")
+            .with_status(200)
+            .create_async()
+            .await;
+        let mock_c_get = server.mock("GET", "/c").expect(0).create_async().await;
+
+        let redirect_chain = wayback_client.resolve_redirects(&to_archive).await.unwrap();
+        assert_eq!(redirect_chain.hops(), 2);
+        assert_eq!(redirect_chain.original().as_str(), to_archive);
+        assert_eq!(
+            redirect_chain.final_url().as_str(),
+            format!("{}/c", server.url())
+        );
+        for mock in [mock_a, mock_b_head, mock_b_get, mock_c, mock_c_get] {
+            mock.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_resolve_redirects_loop_and_limit() {
+        let (mut server, wayback_client) = mock_redirect_server(2).await;
+        server
+            .mock("HEAD", "/a")
+            .with_status(302)
+            .with_header("Location", "/b")
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/b")
+            .with_status(302)
+            .with_header("Location", "/a")
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/c")
+            .with_status(302)
+            .with_header("Location", "/d")
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/d")
+            .with_status(302)
+            .with_header("Location", "/e")
+            .create_async()
+            .await;
+        let mock_e = server.mock("HEAD", "/e").expect(0).create_async().await;
+
+        let redirect_chain = wayback_client
+            .resolve_redirects(&format!("{}/a", server.url()))
+            .await
+            .unwrap();
+        assert_eq!(redirect_chain.hops(), 1);
+
+        let redirect_chain = wayback_client
+            .resolve_redirects(&format!("{}/c", server.url()))
+            .await
+            .unwrap();
+        assert_eq!(redirect_chain.hops(), 2);
+        assert_eq!(
+            redirect_chain.final_url().as_str(),
+            format!("{}/e", server.url())
+        );
+        mock_e.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_resolve_redirects_refused_location() {
+        let (mut server, wayback_client) = mock_redirect_server(10).await;
+        server
+            .mock("HEAD", "/a")
+            .with_status(302)
+            .with_header("Location", "ftp://example.com/file")
+            .create_async()
+            .await;
+        // each location is checked against the policy before being requested
+        assert!(matches!(
+            wayback_client
+                .resolve_redirects(&format!("{}/a", server.url()))
+                .await,
+            Err(Error::InvalidUrl(u, InvalidReason::UnsupportedScheme(scheme)))
+                if u == "ftp://example.com/file" && scheme == "ftp"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_redirect_strategy() {
+        let (mut server, wayback_client) = mock_redirect_server(10).await;
+        let original = format!("{}/a", server.url());
+        let final_url = format!("{}/b", server.url());
+        server
+            .mock("HEAD", "/a")
+            .with_status(301)
+            .with_header("Location", "/b")
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/b")
+            .with_status(200)
+            .create_async()
+            .await;
+
+        let mut mocks = Vec::new();
+        for url in [&original, &final_url] {
+            mocks.push(
+                server
+                    .mock("GET", CHECK_PATH)
+                    .match_query(check_query(url))
+                    .with_status(200)
+                    .with_body("[]")
+                    .expect(2)
+                    .create_async()
+                    .await,
+            );
+            mocks.push(
+                server
+                    .mock("GET", &format!("{}{}", ARCHIVE_ROOT_PATH, url)[..])
+                    .with_status(200)
+                    .expect(2)
+                    .create_async()
+                    .await,
+            );
+        }
+
+        // the same URL is checked and saved
+        for (redirect_strategy, expected) in [
+            (RedirectStrategy::Original, vec![&original]),
+            (RedirectStrategy::Final, vec![&final_url]),
+            (RedirectStrategy::Both, vec![&original, &final_url]),
+        ] {
+            let options = ArchiveOptions {
+                redirect_strategy: Some(redirect_strategy),
+                ..Default::default()
+            };
+            let report = wayback_client
+                .archive_url_with_options(&original, &options)
+                .await
+                .unwrap();
+            assert_eq!(report.redirect_chain.hops(), 1);
+            let urls: Vec<String> = report
+                .captures
+                .iter()
+                .map(|capture| capture.url.to_string())
+                .collect();
+            assert_eq!(urls.iter().collect::<Vec<_>>(), expected);
+            assert!(report.captures.iter().all(|capture| matches!(
+                capture.outcomes[0].result,
+                Ok(ArchiveResult::Archived(_))
+            )));
+        }
+        for mock in mocks {
+            mock.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_local_warc_does_not_follow_redirects() {
+        let mut server = mockito::Server::new_async().await;
+        let directory =
+            std::env::temp_dir().join(format!("warc-redirect-{}", uuid::Uuid::new_v4()));
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .policy(SchemePolicy::default())
+                .backend(LocalWarc::new(&directory))
+                .build()
+                .unwrap(),
+        );
+        let to_archive = format!("{}/paper", server.url());
+        server
+            .mock("HEAD", "/paper")
+            .with_status(200)
+            .create_async()
+            .await;
+        // the location was never validated, as the redirect only appears on capture
+        server
+            .mock("GET", "/paper")
+            .with_status(302)
+            .with_header("Location", "/private")
+            .create_async()
+            .await;
+        let private_mock = server
+            .mock("GET", "/private")
+            .expect(0)
+            .create_async()
+            .await;
+
+        let report = wayback_client.archive_url(&to_archive).await.unwrap();
+        assert!(matches!(
+            report.captures[0].outcomes[0].result,
+            Ok(ArchiveResult::Archived(_))
+        ));
+        private_mock.assert_async().await;
+
+        // the redirect is indexed under the requested URL
+        let index = std::fs::read_to_string(directory.join("archive.cdxj")).unwrap();
+        assert!(index.starts_with(&format!(
+            "{} ",
+            warc::surt(&Url::parse(&to_archive).unwrap())
+        )));
+        assert!(index.contains(r#""status":"302""#));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_pending_capture() {
+        let to_archive = "https://example.com/";
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .capture_poll_interval(Duration::from_millis(10))
+                .capture_timeout(Duration::from_millis(100))
+                .build()
+                .unwrap(),
+        );
+
+        server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body("[]")
+            .create_async()
+            .await;
+        server
+            .mock("GET", &format!("{}{}", ARCHIVE_ROOT_PATH, to_archive)[..])
+            .with_status(200)
+            .with_body(r#"{"url": "https://example.com/", "job_id": "spn2-1234"}"#)
+            .create_async()
+            .await;
+        let status_mock = server
+            .mock("GET", "/save/status/spn2-1234")
+            .with_status(200)
+            .with_body(
+                r#"{"status": "success", "timestamp": "20240501100000", "original_url": "https://example.com/"}"#,
+            )
+            .create_async()
+            .await;
+        let report = wayback_client.archive_url(to_archive).await.unwrap();
+        assert!(matches!(
+            &report.captures[0].outcomes[0].result,
+            Ok(ArchiveResult::Archived(archive_url))
+                if archive_url == "https://web.archive.org/web/20240501100000/https://example.com/"
+        ));
+        status_mock.assert_async().await;
+
+        // still pending when the capture timeout is reached
+        server
+            .mock("GET", "/save/status/spn2-1234")
+            .with_status(200)
+            .with_body(r#"{"status": "pending"}"#)
+            .create_async()
+            .await;
+        assert!(matches!(
+            wayback_client.archive_url(to_archive).await,
+            Err(Error::CapturePending { job_id, .. }) if job_id == "spn2-1234"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_perma_cc() {
+        let to_archive = "https://example.com/";
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .backend(PermaCc::new("secret").endpoint(format!("{}/v1/", server.url())))
+                .capture_poll_interval(Duration::from_millis(10))
+                .build()
+                .unwrap(),
+        );
+
+        let mocks = [
+            server
+                .mock("GET", "/v1/archives/")
+                .match_query(Matcher::Any)
+                .with_status(200)
+                .with_body(r#"{"objects": []}"#)
+                .create_async()
+                .await,
+            server
+                .mock("POST", "/v1/archives/")
+                .match_header("authorization", "ApiKey secret")
+                .with_status(201)
+                .with_body(r#"{"guid": "ABCD-1234", "creation_timestamp": "2024-05-01T10:00:00Z"}"#)
+                .create_async()
+                .await,
+            server
+                .mock("GET", "/v1/capture_jobs/ABCD-1234/")
+                .with_status(200)
+                .with_body(r#"{"status": "completed"}"#)
+                .create_async()
+                .await,
+            // the Wayback Machine is not used
+            server
+                .mock("GET", Matcher::Regex("^/(save|cdx)/".into()))
+                .expect(0)
+                .create_async()
+                .await,
+        ];
+        let report = wayback_client.archive_url(to_archive).await.unwrap();
+        assert!(matches!(
+            &report.captures[0].outcomes[0].result,
+            Ok(ArchiveResult::Archived(archive_url)) if archive_url == "https://perma.cc/ABCD-1234"
+        ));
+        for mock in mocks {
+            mock.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_quorum() {
+        let to_archive = "https://example.com/";
+        let mut server = mockito::Server::new_async().await;
+        let [at_least_one, all] = [Quorum::AtLeast(1), Quorum::All].map(|quorum| {
+            WaybackMachineClient::new(
+                mock_config(&server)
+                    .backend(WaybackMachine::new(
+                        format!("{}{}", server.url(), ARCHIVE_ROOT_PATH),
+                        FreshnessCheck::Cdx(format!("{}{}", server.url(), CHECK_ROOT_PATH)),
+                    ))
+                    .backend(PermaCc::new("secret").endpoint(format!("{}/v1/", server.url())))
+                    .quorum(quorum)
+                    .capture_poll_interval(Duration::from_millis(10))
+                    .build()
+                    .unwrap(),
+            )
+        });
+
+        server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body("[]")
+            .create_async()
+            .await;
+        // the Wayback Machine refuses the capture, Perma.cc completes it
+        server
+            .mock("GET", &format!("{}{}", ARCHIVE_ROOT_PATH, to_archive)[..])
+            .with_status(403)
+            .with_body("<p>This URL has been excluded from the Wayback Machine.</p>")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v1/archives/")
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(r#"{"objects": []}"#)
+            .create_async()
+            .await;
+        server
+            .mock("POST", "/v1/archives/")
+            .with_status(201)
+            .with_body(r#"{"guid": "ABCD-1234", "creation_timestamp": "2024-05-01T10:00:00Z"}"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/v1/capture_jobs/ABCD-1234/")
+            .with_status(200)
+            .with_body(r#"{"status": "completed"}"#)
+            .create_async()
+            .await;
+
+        let report = at_least_one.archive_url(to_archive).await.unwrap();
+        let outcomes = &report.captures[0].outcomes;
+        assert_eq!(outcomes[0].archive, "Wayback Machine");
+        assert!(matches!(
+            outcomes[0].result,
+            Err(Error::CannotArchive {
+                cause: CaptureFailure::ExcludedBySiteOwner,
+                ..
+            })
+        ));
+        assert_eq!(outcomes[1].archive, "Perma.cc");
+        assert!(matches!(
+            &outcomes[1].result,
+            Ok(ArchiveResult::Archived(archive_url)) if archive_url == "https://perma.cc/ABCD-1234"
+        ));
+
+        match all.archive_url(to_archive).await {
+            Err(Error::QuorumNotReached {
+                required: 2,
+                report,
+                ..
+            }) => {
+                let failures: Vec<_> = report.failures().collect();
+                assert_eq!(failures.len(), 1);
+                assert_eq!(failures[0].1, "Wayback Machine");
+                // the archives that succeeded are reported too
+                assert!(matches!(
+                    &report.captures[0].outcomes[1].result,
+                    Ok(ArchiveResult::Archived(archive_url)) if archive_url == "https://perma.cc/ABCD-1234"
+                ));
+            }
+            _ => panic!("expected the quorum not to be reached"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check_recent_archive_exists_success() {
+        let to_archive = "https://example.com/";
+        let snapshot_timestamp = (Utc::now() - TimeDelta::try_days(1).unwrap())
+            .format("%Y%m%d%H%M%S")
+            .to_string();
+        let (mut server, wayback_client) = mock_server().await;
+
+        let snapshot: Value = json!([["timestamp"], [snapshot_timestamp]]);
+        let mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .create_async()
+            .await;
+
+        assert!(wayback_client
+            .check_recent_archive_exists(to_archive, &ArchiveOptions::default())
+            .await
+            .is_ok());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_recent_archive_exists_old_snapshot() {
+        let to_archive = "https://example.com/";
+        let snapshot_timestamp = (Utc::now() - TimeDelta::try_days(100).unwrap())
+            .format("%Y%m%d%H%M%S")
+            .to_string();
+        let (mut server, wayback_client) = mock_server().await;
+
+        let snapshot: Value = json!([["timestamp"], [snapshot_timestamp]]);
+        let mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .create_async()
+            .await;
+
+        assert!(wayback_client
+            .check_recent_archive_exists(to_archive, &ArchiveOptions::default())
+            .await
+            .is_err());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_recent_archive_exists_advancing_clock() {
+        let to_archive = "https://example.com/";
+        let now = Arc::new(Mutex::new(
+            NaiveDateTime::parse_from_str("20230301000000", "%Y%m%d%H%M%S")
+                .unwrap()
+                .and_utc(),
+        ));
+        let mut server = mockito::Server::new_async().await;
+        let clock = now.clone();
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .clock(move || *clock.lock().unwrap())
+                .build()
+                .unwrap(),
+        );
+
+        let snapshot: Value = json!([["timestamp"], ["20230227054528"]]);
+        let mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .expect(2)
+            .create_async()
+            .await;
+
+        assert!(wayback_client
+            .check_recent_archive_exists(to_archive, &ArchiveOptions::default())
+            .await
+            .is_ok());
+        // the same client, two months later
+        *now.lock().unwrap() += TimeDelta::try_days(60).unwrap();
+        assert!(wayback_client
+            .check_recent_archive_exists(to_archive, &ArchiveOptions::default())
+            .await
+            .is_err());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_recent_archive_exists_threshold_override() {
+        let to_archive = "https://example.com/";
+        let snapshot_timestamp = (Utc::now() - TimeDelta::try_days(3).unwrap())
+            .format("%Y%m%d%H%M%S")
+            .to_string();
+        let (mut server, wayback_client) = mock_server().await;
+
+        let snapshot: Value = json!([["timestamp"], [snapshot_timestamp]]);
+        let mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .expect(2)
+            .create_async()
+            .await;
+
+        assert!(wayback_client
+            .check_recent_archive_exists(to_archive, &ArchiveOptions::default())
+            .await
+            .is_ok());
+        let options = ArchiveOptions {
+            archive_threshold: Some(TimeDelta::try_days(1).unwrap()),
+            ..Default::default()
+        };
+        assert!(wayback_client
+            .check_recent_archive_exists(to_archive, &options)
+            .await
+            .is_err());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_recent_archive_exists_freshness_rules() {
+        let snapshot_timestamp = (Utc::now() - TimeDelta::try_days(3).unwrap())
+            .format("%Y%m%d%H%M%S")
+            .to_string();
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .freshness_rules(
+                    FreshnessRules::new()
+                        .host("example.gov", Freshness::AnySnapshot)
+                        .host(
+                            "news.example.com",
+                            Freshness::MaxAge(TimeDelta::try_hours(1).unwrap()),
+                        ),
+                )
+                .build()
+                .unwrap(),
+        );
+
+        let snapshot: Value = json!([["timestamp"], [snapshot_timestamp]]);
+        let mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(Matcher::Any)
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .expect(3)
+            .create_async()
+            .await;
+
+        // only news needs re-archiving, the default threshold applies elsewhere
+        for (url, is_recent) in [
+            ("https://www.example.gov/report", true),
+            ("https://news.example.com/today", false),
+            ("https://example.com/", true),
+        ] {
+            assert_eq!(
+                wayback_client
+                    .check_recent_archive_exists(url, &ArchiveOptions::default())
+                    .await
+                    .is_ok(),
+                is_recent,
+                "{}",
+                url
+            );
+        }
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_url() {
+        let (mut server, wayback_client) = mock_redirect_server(10).await;
+        let save_mock = server
+            .mock("GET", Matcher::Regex(format!("^{}", ARCHIVE_ROOT_PATH)))
+            .expect(0)
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/moved")
+            .with_status(301)
+            .with_header("Location", "/here")
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/here")
+            .with_status(200)
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/broken")
+            .with_status(500)
+            .create_async()
+            .await;
+        // server errors of HEAD requests are not asked again with a GET
+        let broken_get = server.mock("GET", "/broken").expect(0).create_async().await;
+        let moved = format!("{}/moved", server.url());
+        server
+            .mock("GET", CHECK_PATH)
+            .match_query(Matcher::UrlEncoded("url".into(), moved.clone()))
+            .with_status(200)
+            .with_body(r#"[["timestamp"],["20190530120000"]]"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", CHECK_PATH)
+            .match_query(Matcher::UrlEncoded(
+                "url".into(),
+                format!("{}/broken", server.url()),
+            ))
+            .with_status(200)
+            .with_body("[]")
+            .create_async()
+            .await;
+
+        let check = wayback_client
+            .check_url(&moved, &ArchiveOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(check.status, LinkStatus::Redirected);
+        assert_eq!(check.redirect_chain.hops(), 1);
+        assert!(check.snapshot.unwrap().is_some());
+
+        let check = wayback_client
+            .check_url(
+                &format!("{}/broken", server.url()),
+                &ArchiveOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            check.status,
+            LinkStatus::ServerError(StatusCode::INTERNAL_SERVER_ERROR)
+        );
+        assert!(check.status.is_broken());
+        assert!(check.snapshot.unwrap().is_none());
+        broken_get.assert_async().await;
+        save_mock.assert_async().await;
+    }
+
+    #[test]
+    fn test_is_recent_relative_to_publication() {
+        let url = Url::parse("https://doi.org/10.11647/obp.0001").unwrap();
+        let date = |date: &str| NaiveDateTime::parse_from_str(date, "%Y%m%d%H%M%S").unwrap();
+        let wayback_client = WaybackMachineClient::new(
+            ClientConfig::builder()
+                .freshness_rules(FreshnessRules::new().host(
+                    "doi.org",
+                    Freshness::NearReference(TimeDelta::try_days(180).unwrap()),
+                ))
+                .build()
+                .unwrap(),
+        );
+        let snapshot = Snapshot {
+            url: "https://web.archive.org/web/20190301000000/https://doi.org/10.11647/obp.0001"
+                .into(),
+            timestamp: date("20190301000000"),
+        };
+        let published = |reference_date| ArchiveOptions {
+            reference_date: Some(reference_date),
+            ..Default::default()
+        };
+
+        // taken three months before publication
+        assert!(wayback_client.is_recent(&url, &snapshot, &published(date("20190601000000"))));
+        // taken three months after publication
+        assert!(wayback_client.is_recent(&url, &snapshot, &published(date("20181201000000"))));
+        // taken a year before publication
+        assert!(!wayback_client.is_recent(&url, &snapshot, &published(date("20200301000000"))));
+        // taken years after publication
+        assert!(!wayback_client.is_recent(&url, &snapshot, &published(date("20150301000000"))));
+        // without a publication date, the age is counted from now
+        assert!(!wayback_client.is_recent(&url, &snapshot, &ArchiveOptions::default()));
+    }
+
+    #[tokio::test]
+    async fn test_check_recent_archive_near_reference() {
+        let to_archive = "https://doi.org/10.11647/obp.0001";
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .reference_window(TimeDelta::try_days(180).unwrap())
+                .build()
+                .unwrap(),
+        );
+        // the latest snapshot, taken years later, is not looked at
+        let latest_mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body(r#"[["timestamp"],["20240101000000"]]"#)
+            .expect(0)
+            .create_async()
+            .await;
+        let closest_mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("url".into(), to_archive.into()),
+                Matcher::UrlEncoded("closest".into(), "20190601000000".into()),
+            ]))
+            .with_status(200)
+            .with_body(r#"[["timestamp"],["20190530120000"]]"#)
+            .create_async()
+            .await;
+        let published = |date: &str| ArchiveOptions {
+            reference_date: NaiveDateTime::parse_from_str(date, "%Y%m%d%H%M%S").ok(),
+            ..Default::default()
+        };
+
+        let snapshot = wayback_client
+            .check_recent_archive_exists(to_archive, &published("20190601000000"))
+            .await
+            .unwrap();
+        assert_eq!(snapshot.timestamp.to_string(), "2019-05-30 12:00:00");
+        closest_mock.assert_async().await;
+        latest_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_latest_snapshot_cdx() {
+        let to_archive = "https://example.com/";
+        let snapshot_timestamp = "20230227054528";
+        let (mut server, wayback_client) = mock_server().await;
+
+        let snapshot: Value = json!([["timestamp"], [snapshot_timestamp]]);
+        let mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .create_async()
+            .await;
+
+        let snapshot = wayback_client
+            .latest_snapshot(to_archive)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            snapshot.url,
+            format!("{}/web/{}/{}", server.url(), snapshot_timestamp, to_archive)
+        );
+        assert_eq!(
+            snapshot.timestamp.format("%Y%m%d%H%M%S").to_string(),
+            snapshot_timestamp
+        );
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_latest_snapshot_availability() {
+        let to_archive = "https://example.com/?a=1&b=2";
+        let snapshot_timestamp = "20230227054528";
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .freshness_check(FreshnessCheck::Availability(format!(
+                    "{}{}",
+                    server.url(),
+                    AVAILABILITY_ROOT_PATH
+                )))
+                .build()
+                .unwrap(),
+        );
+
+        let snapshot_url = format!(
+            "http://web.archive.org/web/{}/{}",
+            snapshot_timestamp, to_archive
+        );
+        let snapshot: Value = json!({
+            "url": to_archive,
+            "archived_snapshots": {
+                "closest": {
+                    "status": "200",
+                    "available": true,
+                    "url": snapshot_url,
+                    "timestamp": snapshot_timestamp
+                }
+            }
+        });
+        let mock = server
+            .mock("GET", AVAILABILITY_PATH)
+            .match_query(Matcher::UrlEncoded("url".into(), to_archive.into()))
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .create_async()
+            .await;
+
+        let snapshot = wayback_client
+            .latest_snapshot(to_archive)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.url, snapshot_url);
+        assert_eq!(
+            snapshot.timestamp.format("%Y%m%d%H%M%S").to_string(),
+            snapshot_timestamp
+        );
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_latest_snapshot_availability_no_snapshot() {
+        let to_archive = "https://example.com/";
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .freshness_check(FreshnessCheck::Availability(format!(
+                    "{}{}",
+                    server.url(),
+                    AVAILABILITY_ROOT_PATH
+                )))
+                .build()
+                .unwrap(),
+        );
+
+        let snapshot: Value = json!({"url": to_archive, "archived_snapshots": {}});
+        let mock = server
+            .mock("GET", AVAILABILITY_PATH)
+            .match_query(Matcher::UrlEncoded("url".into(), to_archive.into()))
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .create_async()
+            .await;
+
+        assert!(wayback_client
+            .latest_snapshot(to_archive)
+            .await
+            .unwrap()
+            .is_none());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_latest_snapshot_timegate() {
+        let to_archive = "https://example.com/";
+        let snapshot_timestamp = "20230227054528";
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .freshness_check(FreshnessCheck::TimeGate(format!(
+                    "{}{}",
+                    server.url(),
+                    TIMEGATE_ROOT_PATH
+                )))
+                .build()
+                .unwrap(),
+        );
+
+        let memento_path = format!(
+            "{}{}/{}",
+            TIMEGATE_ROOT_PATH, snapshot_timestamp, to_archive
+        );
+        let mock1 = server
+            .mock("HEAD", &format!("{}{}", TIMEGATE_ROOT_PATH, to_archive)[..])
+            .with_status(302)
+            .with_header("Location", &memento_path)
+            .create_async()
+            .await;
+        let mock2 = server
+            .mock("HEAD", &memento_path[..])
+            .with_status(200)
+            .with_header("Memento-Datetime", "Mon, 27 Feb 2023 05:45:28 GMT")
+            .create_async()
+            .await;
+
+        let snapshot = wayback_client
+            .latest_snapshot(to_archive)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.url, format!("{}{}", server.url(), memento_path));
+        assert_eq!(
+            snapshot.timestamp.format("%Y%m%d%H%M%S").to_string(),
+            snapshot_timestamp
+        );
+        mock1.assert_async().await;
+        mock2.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_latest_snapshot_timegate_not_found() {
+        let to_archive = "https://example.com/";
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .freshness_check(FreshnessCheck::TimeGate(format!(
+                    "{}{}",
+                    server.url(),
+                    TIMEGATE_ROOT_PATH
+                )))
+                .build()
+                .unwrap(),
+        );
+
+        let mock = server
+            .mock("HEAD", &format!("{}{}", TIMEGATE_ROOT_PATH, to_archive)[..])
+            .with_status(404)
+            .create_async()
+            .await;
+
+        assert!(wayback_client
+            .latest_snapshot(to_archive)
+            .await
+            .unwrap()
+            .is_none());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_check_recent_archive_exists_no_snapshot() {
+        let to_archive = "https://example.com/";
+        let (mut server, wayback_client) = mock_server().await;
+
+        let snapshot: Value = json!([]);
+        let mock = server
+            .mock("GET", CHECK_PATH)
+            .match_query(check_query(to_archive))
+            .with_status(200)
+            .with_body(snapshot.to_string())
+            .create_async()
+            .await;
+
+        assert!(wayback_client
+            .check_recent_archive_exists(to_archive, &ArchiveOptions::default())
+            .await
+            .is_err());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_dead_link() {
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .policy(SchemePolicy::default())
+                .closest_for_dead_links(true)
+                .build()
+                .unwrap(),
+        );
+        for method in ["HEAD", "GET"] {
+            server
+                .mock(method, Matcher::Regex("^/(gone|lost)$".into()))
+                .with_status(404)
+                .create_async()
+                .await;
+        }
+        let save_mock = server
+            .mock("GET", Matcher::Regex(format!("^{}", ARCHIVE_ROOT_PATH)))
+            .expect(0)
+            .create_async()
+            .await;
+        let closest_query = |url: &str| {
+            Matcher::AllOf(vec![
+                Matcher::UrlEncoded("url".into(), url.into()),
+                Matcher::UrlEncoded("closest".into(), "20190601000000".into()),
+                Matcher::UrlEncoded("sort".into(), "closest".into()),
+                Matcher::UrlEncoded("filter".into(), "statuscode:2..".into()),
+                Matcher::UrlEncoded("limit".into(), "1".into()),
+            ])
+        };
+        let gone = format!("{}/gone", server.url());
+        let lost = format!("{}/lost", server.url());
+        server
+            .mock("GET", CHECK_PATH)
+            .match_query(closest_query(&gone))
+            .with_status(200)
+            .with_body(r#"[["timestamp"],["20190530120000"]]"#)
+            .create_async()
+            .await;
+        server
+            .mock("GET", CHECK_PATH)
+            .match_query(closest_query(&lost))
+            .with_status(200)
+            .with_body("[]")
+            .create_async()
+            .await;
+
+        let options = ArchiveOptions {
+            reference_date: NaiveDateTime::parse_from_str(
+                "2019-06-01 00:00:00",
+                "%Y-%m-%d %H:%M:%S",
+            )
+            .ok(),
+            ..Default::default()
+        };
+        let report = wayback_client
+            .archive_url_with_options(&gone, &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            report.redirect_chain.target_status(),
+            TargetStatus::Answered(StatusCode::NOT_FOUND)
+        );
+        match &report.captures[0].outcomes[0].result {
+            Ok(ArchiveResult::ClosestSnapshot(snapshot)) => {
+                assert_eq!(snapshot.timestamp.to_string(), "2019-05-30 12:00:00");
+                assert!(snapshot.url.ends_with(&format!("20190530120000/{}", gone)));
+            }
+            _ => panic!("expected the closest snapshot"),
+        }
+
+        assert!(matches!(
+            wayback_client
+                .archive_url_with_options(&lost, &options)
+                .await,
+            Err(Error::DeadUrl { .. })
+        ));
+        save_mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_verify_capture() {
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .policy(SchemePolicy::default())
+                .verify_captures(true)
+                .build()
+                .unwrap(),
+        );
+        let page = "<title>The Theory of Stuff</title><p>A book about all kinds of things.</p>";
+        for (path, body) in [("/book", page), ("/blocked", "<p>The book, in full.</p>")] {
+            let to_archive = format!("{}{}", server.url(), path);
+            server
+                .mock("HEAD", path)
+                .with_status(200)
+                .create_async()
+                .await;
+            server
+                .mock("GET", path)
+                .with_status(200)
+                .with_header("Content-Type", "text/html")
+                .with_body(body)
+                .create_async()
+                .await;
+            server
+                .mock("GET", CHECK_PATH)
+                .match_query(check_query(&to_archive))
+                .with_status(200)
+                .with_body("[]")
+                .create_async()
+                .await;
+            let replay = format!("/web/20240501100000/{}", to_archive);
+            server
+                .mock("GET", &format!("{}{}", ARCHIVE_ROOT_PATH, to_archive)[..])
+                .with_status(302)
+                .with_header("Location", &replay)
+                .create_async()
+                .await;
+            server
+                .mock("GET", &replay[..])
+                .with_status(200)
+                .create_async()
+                .await;
+        }
+        let raw_replay = |path: &str| format!("/web/20240501100000id_/{}{}", server.url(), path);
+        let (book, blocked) = (raw_replay("/book"), raw_replay("/blocked"));
+        let book_replay = server
+            .mock("GET", &book[..])
+            .with_status(200)
+            .with_header("Content-Type", "text/html")
+            .with_body(page)
+            .create_async()
+            .await;
+        server
+            .mock("GET", &blocked[..])
+            .with_status(200)
+            .with_header("Content-Type", "text/html")
+            .with_body("<p>Please complete the CAPTCHA to continue.</p>")
+            .create_async()
+            .await;
+
+        let report = wayback_client
+            .archive_url(&format!("{}/book", server.url()))
+            .await
+            .unwrap();
+        let outcome = &report.captures[0].outcomes[0];
+        assert!(matches!(outcome.result, Ok(ArchiveResult::Archived(_))));
+        assert_eq!(
+            outcome.verification,
+            Some(CaptureVerification::Verified { similarity: 1.0 })
+        );
+        book_replay.assert_async().await;
+
+        let report = wayback_client
+            .archive_url(&format!("{}/blocked", server.url()))
+            .await
+            .unwrap();
+        assert_eq!(
+            report.captures[0].outcomes[0].verification,
+            Some(CaptureVerification::Captcha)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_archive_url_soft_404() {
+        let mut server = mockito::Server::new_async().await;
+        let wayback_client = WaybackMachineClient::new(
+            mock_config(&server)
+                .policy(SchemePolicy::default())
+                .soft_404_detection(Soft404Detection::Skip)
+                .build()
+                .unwrap(),
+        );
+        server
+            .mock("HEAD", "/books/obp-0001")
+            .with_status(301)
+            .with_header("Location", "/")
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/")
+            .with_status(200)
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/removed")
+            .with_status(200)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/removed")
+            .with_status(200)
+            .with_body("<title>Catalogue</title><h1>Sorry, this page no longer exists.</h1>")
+            .create_async()
+            .await;
+        let save_mock = server
+            .mock("GET", Matcher::Regex(format!("^{}", ARCHIVE_ROOT_PATH)))
+            .expect(0)
+            .create_async()
+            .await;
+
+        let url = format!("{}/books/obp-0001", server.url());
+        match wayback_client.archive_url(&url).await {
+            Err(Error::LikelySoft404 {
+                url: skipped,
+                soft_404,
+            }) => {
+                assert_eq!(skipped, url);
+                assert_eq!(soft_404, Soft404::RedirectToHomepage);
+            }
+            _ => panic!("expected a likely soft 404"),
+        }
+        assert!(matches!(
+            wayback_client
+                .archive_url(&format!("{}/removed", server.url()))
+                .await,
+            Err(Error::LikelySoft404 {
+                soft_404: Soft404::ErrorMessage(_),
+                ..
+            })
+        ));
+        save_mock.assert_async().await;
     }
 }
@@ -0,0 +1,131 @@
+use crate::redirect::{RedirectChain, TargetStatus};
+use crate::{Error, RequestErrorKind, Snapshot};
+use reqwest::StatusCode;
+use std::fmt;
+
+/// Health of a link, classified from how its final location answered
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LinkStatus {
+    /// The URL answers successfully
+    Ok,
+    /// The URL redirects to a location answering successfully
+    Redirected,
+    /// The location answers `404 Not Found` or `410 Gone`
+    Gone(StatusCode),
+    /// The location answers with another client error
+    ClientError(StatusCode),
+    /// The location answers with a server error
+    ServerError(StatusCode),
+    /// No response was received: DNS or TLS failure, timeout...
+    Unreachable(RequestErrorKind),
+    /// The redirects loop, or are too many to be followed
+    TooManyRedirects,
+}
+
+/// Result of checking a link without archiving it
+pub struct LinkCheck {
+    /// Redirects followed from the URL
+    pub redirect_chain: RedirectChain,
+    /// Health of the link
+    pub status: LinkStatus,
+    /// Successful snapshot closest to the reference date, if any
+    pub snapshot: Result<Option<Snapshot>, Error>,
+}
+
+impl LinkStatus {
+    /// Classifies the final location of a redirect chain
+    pub fn of(redirect_chain: &RedirectChain) -> Self {
+        match redirect_chain.target_status() {
+            TargetStatus::Answered(status)
+                if status == StatusCode::NOT_FOUND || status == StatusCode::GONE =>
+            {
+                LinkStatus::Gone(status)
+            }
+            TargetStatus::Answered(status) if status.is_client_error() => {
+                LinkStatus::ClientError(status)
+            }
+            TargetStatus::Answered(status) if status.is_server_error() => {
+                LinkStatus::ServerError(status)
+            }
+            TargetStatus::Answered(status) if status.is_redirection() => {
+                LinkStatus::TooManyRedirects
+            }
+            TargetStatus::Answered(_) if redirect_chain.is_redirected() => LinkStatus::Redirected,
+            TargetStatus::Answered(_) => LinkStatus::Ok,
+            TargetStatus::Unreachable(kind) => LinkStatus::Unreachable(kind),
+            TargetStatus::Unchecked => LinkStatus::TooManyRedirects,
+        }
+    }
+
+    /// Returns `true` if the link does not lead to a page
+    pub fn is_broken(&self) -> bool {
+        !matches!(self, LinkStatus::Ok | LinkStatus::Redirected)
+    }
+}
+
+impl fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkStatus::Ok => write!(f, "OK"),
+            LinkStatus::Redirected => write!(f, "redirected"),
+            LinkStatus::Gone(status)
+            | LinkStatus::ClientError(status)
+            | LinkStatus::ServerError(status) => write!(f, "{}", status),
+            LinkStatus::Unreachable(kind) => write!(f, "{}", kind),
+            LinkStatus::TooManyRedirects => write!(f, "too many redirects"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use url::Url;
+
+    fn chain(hops: usize, status: TargetStatus) -> RedirectChain {
+        let mut chain = RedirectChain::new(Url::parse("https://example.com/0").unwrap());
+        for hop in 1..=hops {
+            chain.push(Url::parse(&format!("https://example.com/{}", hop)).unwrap());
+        }
+        chain.set_status(status);
+        chain
+    }
+
+    #[test]
+    fn classify() {
+        for (chain, expected) in [
+            (
+                chain(0, TargetStatus::Answered(StatusCode::OK)),
+                LinkStatus::Ok,
+            ),
+            (
+                chain(1, TargetStatus::Answered(StatusCode::OK)),
+                LinkStatus::Redirected,
+            ),
+            (
+                chain(0, TargetStatus::Answered(StatusCode::GONE)),
+                LinkStatus::Gone(StatusCode::GONE),
+            ),
+            (
+                chain(0, TargetStatus::Answered(StatusCode::FORBIDDEN)),
+                LinkStatus::ClientError(StatusCode::FORBIDDEN),
+            ),
+            (
+                chain(1, TargetStatus::Answered(StatusCode::BAD_GATEWAY)),
+                LinkStatus::ServerError(StatusCode::BAD_GATEWAY),
+            ),
+            (
+                chain(0, TargetStatus::Unreachable(RequestErrorKind::Tls)),
+                LinkStatus::Unreachable(RequestErrorKind::Tls),
+            ),
+            (
+                chain(10, TargetStatus::Unchecked),
+                LinkStatus::TooManyRedirects,
+            ),
+        ] {
+            assert_eq!(LinkStatus::of(&chain), expected, "{}", chain);
+        }
+        assert!(!LinkStatus::Redirected.is_broken());
+        assert!(LinkStatus::Unreachable(RequestErrorKind::Timeout).is_broken());
+    }
+}
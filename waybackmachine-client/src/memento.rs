@@ -0,0 +1,333 @@
+use crate::freshness::{refused, send_check};
+use crate::{ArchivableUrl, Error, Snapshot, WAYBACK_MACHINE_TIMEGATE_ENDPOINT};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use reqwest::header::LINK;
+use reqwest::StatusCode;
+use reqwest_middleware::ClientWithMiddleware;
+use url::Url;
+
+/// Link-format TimeMap of the Wayback Machine
+pub const WAYBACK_MACHINE_TIMEMAP_ENDPOINT: &str = "https://web.archive.org/web/timemap/link/";
+/// TimeGate of the Time Travel aggregator, covering many archives
+pub const TIME_TRAVEL_TIMEGATE_ENDPOINT: &str = "https://timetravel.mementoweb.org/timegate/";
+/// Link-format TimeMap of the Time Travel aggregator
+pub const TIME_TRAVEL_TIMEMAP_ENDPOINT: &str = "https://timetravel.mementoweb.org/timemap/link/";
+
+/// Format of the HTTP dates used by `Accept-Datetime` and `Memento-Datetime`
+const HTTP_DATE_FORMAT: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+/// Memento-compliant archive or aggregator, as described in RFC 7089.
+///
+/// The URL is appended verbatim to both endpoints, as most services expect.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MementoService {
+    /// Endpoint of the TimeGate, redirecting to the memento closest to a given time
+    pub timegate: String,
+    /// Endpoint of the `application/link-format` TimeMap, listing every memento
+    pub timemap: String,
+}
+
+/// Link of a link-format document or `Link` header
+#[derive(Debug, Default)]
+struct Link {
+    target: String,
+    rel: Vec<String>,
+    datetime: Option<String>,
+}
+
+impl MementoService {
+    /// Constructs a service from its TimeGate and TimeMap endpoints
+    pub fn new(timegate: impl Into<String>, timemap: impl Into<String>) -> Self {
+        MementoService {
+            timegate: timegate.into(),
+            timemap: timemap.into(),
+        }
+    }
+
+    /// The Wayback Machine
+    pub fn wayback() -> Self {
+        MementoService::new(
+            WAYBACK_MACHINE_TIMEGATE_ENDPOINT,
+            WAYBACK_MACHINE_TIMEMAP_ENDPOINT,
+        )
+    }
+
+    /// The Time Travel aggregator
+    pub fn time_travel() -> Self {
+        MementoService::new(TIME_TRAVEL_TIMEGATE_ENDPOINT, TIME_TRAVEL_TIMEMAP_ENDPOINT)
+    }
+
+    /// Lists the mementos of the URL, oldest first.
+    ///
+    /// Returns an empty list if the URL has no memento.
+    pub(crate) async fn timemap(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_check: &ArchivableUrl,
+    ) -> Result<Vec<Snapshot>, Error> {
+        let response =
+            send_check(http_client.get(format!("{}{}", self.timemap, to_check.without_fragment())))
+                .await?;
+        // services answer 404 for URLs that were never archived
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        if !response.status().is_success() {
+            return Err(refused(&response));
+        }
+        let base = response.url().clone();
+        let document = response
+            .text()
+            .await
+            .map_err(|e| Error::cannot_check("invalid TimeMap", e))?;
+        parse_timemap(&document, &base)
+    }
+
+    /// Looks up the memento of the URL closest to the given time, or the latest one
+    pub(crate) async fn memento_at(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_check: &ArchivableUrl,
+        accept_datetime: Option<DateTime<Utc>>,
+    ) -> Result<Option<Snapshot>, Error> {
+        timegate_memento(http_client, &self.timegate, to_check, accept_datetime).await
+    }
+}
+
+/// Follows the TimeGate, which redirects to the memento closest to `Accept-Datetime`,
+/// or to the latest memento without it.
+///
+/// A `HEAD` request is enough, as the capture time is given in the `Memento-Datetime`
+/// header of the memento. TimeGates answering with the memento links instead are
+/// read from their `Link` header.
+pub(crate) async fn timegate_memento(
+    http_client: &ClientWithMiddleware,
+    timegate: &str,
+    to_check: &ArchivableUrl,
+    accept_datetime: Option<DateTime<Utc>>,
+) -> Result<Option<Snapshot>, Error> {
+    let mut request = http_client.head(format!("{}{}", timegate, to_check.without_fragment()));
+    if let Some(accept_datetime) = accept_datetime {
+        request = request.header(
+            "Accept-Datetime",
+            accept_datetime.format(HTTP_DATE_FORMAT).to_string(),
+        );
+    }
+    let response = send_check(request).await?;
+    // the TimeGate answers 404 for URLs that were never archived
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(refused(&response));
+    }
+
+    if let Some(memento_datetime) = response.headers().get("Memento-Datetime") {
+        let memento_datetime = memento_datetime
+            .to_str()
+            .map_err(|e| Error::cannot_check("invalid Memento-Datetime", e))?;
+        return Ok(Some(Snapshot {
+            url: response.url().to_string(),
+            timestamp: parse_http_date(memento_datetime)?,
+        }));
+    }
+    let mut mementos = Vec::new();
+    for link in response.headers().get_all(LINK) {
+        let link = link
+            .to_str()
+            .map_err(|e| Error::cannot_check("invalid Link header", e))?;
+        mementos.extend(parse_timemap(link, response.url())?);
+    }
+    Ok(closest(mementos, accept_datetime))
+}
+
+/// Returns the memento closest to the given time, or the latest one
+pub(crate) fn closest(
+    mementos: Vec<Snapshot>,
+    datetime: Option<DateTime<Utc>>,
+) -> Option<Snapshot> {
+    match datetime {
+        Some(datetime) => mementos
+            .into_iter()
+            .min_by_key(|memento| (memento.timestamp - datetime.naive_utc()).abs()),
+        None => mementos.into_iter().max_by_key(|memento| memento.timestamp),
+    }
+}
+
+/// Reads the mementos listed in an `application/link-format` document (RFC 6690),
+/// such as a TimeMap or a `Link` header, oldest first.
+///
+/// Links whose relation includes `memento` are kept, resolved against `base`.
+pub fn parse_timemap(document: &str, base: &Url) -> Result<Vec<Snapshot>, Error> {
+    let mut mementos = Vec::new();
+    for link in parse_links(document) {
+        if !link.rel.iter().any(|rel| rel == "memento") {
+            continue;
+        }
+        let url = base
+            .join(&link.target)
+            .map_err(|e| Error::cannot_check("invalid memento URL", e))?;
+        let datetime = link.datetime.ok_or_else(|| Error::CannotCheckArchive {
+            reason: format!("memento without datetime: {}", url),
+            source: None,
+        })?;
+        mementos.push(Snapshot {
+            url: url.to_string(),
+            timestamp: parse_http_date(&datetime)?,
+        });
+    }
+    mementos.sort_by_key(|memento| memento.timestamp);
+    Ok(mementos)
+}
+
+fn parse_http_date(date: &str) -> Result<NaiveDateTime, Error> {
+    Ok(DateTime::parse_from_rfc2822(date.trim())?.naive_utc())
+}
+
+/// Splits a link-format document into links, ignoring separators found
+/// within URIs and quoted values
+fn parse_links(document: &str) -> Vec<Link> {
+    let mut links = Vec::new();
+    let mut link: Option<Link> = None;
+    let mut chars = document.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '<' => {
+                let target: String = chars.by_ref().take_while(|c| *c != '>').collect();
+                links.extend(link.take());
+                link = Some(Link {
+                    target: target.trim().to_string(),
+                    ..Default::default()
+                });
+            }
+            ';' => {
+                let mut param = String::new();
+                let mut in_quotes = false;
+                while let Some(c) = chars.peek() {
+                    match c {
+                        '"' => in_quotes = !in_quotes,
+                        ';' | ',' if !in_quotes => break,
+                        _ => {}
+                    }
+                    param.push(*c);
+                    chars.next();
+                }
+                if let (Some(link), Some((name, value))) = (link.as_mut(), param.split_once('=')) {
+                    let value = value.trim().trim_matches('"');
+                    match name.trim().to_ascii_lowercase().as_str() {
+                        "rel" => link.rel = value.split_whitespace().map(str::to_string).collect(),
+                        "datetime" => link.datetime = Some(value.to_string()),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    links.extend(link);
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEMAP: &str = r#"<http://example.com/>; rel="original",
+<https://web.archive.org/web/timemap/link/http://example.com/>; rel="self"; type="application/link-format"; from="Sat, 20 Jul 2002 13:26:06 GMT",
+<https://web.archive.org/web/20020720132606/http://example.com/>; rel="first memento"; datetime="Sat, 20 Jul 2002 13:26:06 GMT",
+<https://web.archive.org/web/20150321044143/http://example.com/>; rel="memento"; datetime="Sat, 21 Mar 2015 04:41:43 GMT",
+</web/20240501100000/http://example.com/>;rel="last memento";datetime="Wed, 01 May 2024 10:00:00 GMT""#;
+
+    fn base() -> Url {
+        Url::parse("https://web.archive.org/web/timemap/link/http://example.com/").unwrap()
+    }
+
+    #[test]
+    fn timemap() {
+        let mementos = parse_timemap(TIMEMAP, &base()).unwrap();
+        assert_eq!(mementos.len(), 3);
+        assert_eq!(
+            mementos[0].url,
+            "https://web.archive.org/web/20020720132606/http://example.com/"
+        );
+        assert_eq!(mementos[0].timestamp.to_string(), "2002-07-20 13:26:06");
+        // relative URIs are resolved against the TimeMap
+        assert_eq!(
+            mementos[2].url,
+            "https://web.archive.org/web/20240501100000/http://example.com/"
+        );
+    }
+
+    #[test]
+    fn closest_memento() {
+        let mementos = parse_timemap(TIMEMAP, &base()).unwrap();
+        let datetime = DateTime::parse_from_rfc3339("2016-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            closest(mementos.clone(), Some(datetime)).unwrap().url,
+            "https://web.archive.org/web/20150321044143/http://example.com/"
+        );
+        assert_eq!(
+            closest(mementos, None).unwrap().url,
+            "https://web.archive.org/web/20240501100000/http://example.com/"
+        );
+    }
+
+    #[test]
+    fn invalid_datetime() {
+        let timemap = r#"<http://example.com/1>; rel="memento"; datetime="yesterday""#;
+        assert!(parse_timemap(timemap, &base()).is_err());
+    }
+
+    #[tokio::test]
+    async fn timegate_accept_datetime() {
+        let mut server = mockito::Server::new_async().await;
+        let service = MementoService::new(
+            format!("{}/timegate/", server.url()),
+            format!("{}/timemap/", server.url()),
+        );
+        let http_client = ClientWithMiddleware::from(reqwest::Client::new());
+        let to_check = ArchivableUrl::parse("https://example.com/").unwrap();
+
+        let timegate_mock = server
+            .mock("HEAD", "/timegate/https://example.com/")
+            .match_header("accept-datetime", "Sat, 01 Jun 2019 00:00:00 GMT")
+            .with_status(302)
+            .with_header("Location", "/web/20190530120000/https://example.com/")
+            .create_async()
+            .await;
+        server
+            .mock("HEAD", "/web/20190530120000/https://example.com/")
+            .with_status(200)
+            .with_header("Memento-Datetime", "Thu, 30 May 2019 12:00:00 GMT")
+            .create_async()
+            .await;
+        let datetime = DateTime::parse_from_rfc3339("2019-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let memento = service
+            .memento_at(&http_client, &to_check, Some(datetime))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(memento.timestamp.to_string(), "2019-05-30 12:00:00");
+        timegate_mock.assert_async().await;
+
+        server
+            .mock("GET", "/timemap/https://example.com/")
+            .with_status(200)
+            .with_header("Content-Type", "application/link-format")
+            .with_body(TIMEMAP)
+            .create_async()
+            .await;
+        assert_eq!(
+            service
+                .timemap(&http_client, &to_check)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+}
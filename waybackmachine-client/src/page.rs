@@ -0,0 +1,145 @@
+use crate::ratelimit::Endpoint;
+use regex::Regex;
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::StatusCode;
+use reqwest_middleware::ClientWithMiddleware;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::OnceLock;
+use url::Url;
+
+/// Maximum number of bytes of a page read: larger pages are compared by their beginning
+const MAX_PAGE_SIZE: usize = 1024 * 1024;
+
+/// Beginning of a page, downloaded to be inspected
+pub(crate) struct Page {
+    pub(crate) status: StatusCode,
+    pub(crate) location: Option<Url>,
+    pub(crate) content_type: Option<String>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Page {
+    /// Returns the MIME type of the page, without its parameters, in lower case
+    pub(crate) fn mime_type(&self) -> Option<String> {
+        mime_type(self.content_type.as_deref()?)
+    }
+
+    /// Returns `true` if the page is text that words can be compared in
+    pub(crate) fn is_text(&self) -> bool {
+        self.mime_type().as_deref().is_none_or(is_text)
+    }
+
+    pub(crate) fn html(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.body)
+    }
+}
+
+/// Returns the MIME type of a `Content-Type` value, without its parameters, in lower case
+pub(crate) fn mime_type(content_type: &str) -> Option<String> {
+    let mime_type = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    (!mime_type.is_empty()).then_some(mime_type)
+}
+
+/// Returns `true` for text MIME types, such as HTML pages
+pub(crate) fn is_text(mime_type: &str) -> bool {
+    mime_type.starts_with("text/") || mime_type.contains("html") || mime_type.contains("xml")
+}
+
+/// Downloads the beginning of a page, without following redirects unless the
+/// client does
+pub(crate) async fn fetch(
+    client: &ClientWithMiddleware,
+    url: &Url,
+    endpoint: Endpoint,
+) -> Result<Page, reqwest_middleware::Error> {
+    let mut response = client
+        .get(url.clone())
+        .with_extension(endpoint)
+        .send()
+        .await?;
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let location = header(LOCATION).and_then(|location| url.join(&location).ok());
+    let content_type = header(CONTENT_TYPE);
+    let status = response.status();
+    let mut body = Vec::new();
+    while body.len() < MAX_PAGE_SIZE {
+        match response.chunk().await? {
+            Some(chunk) => body.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    // chunks end at arbitrary boundaries, which must not tell two copies apart
+    body.truncate(MAX_PAGE_SIZE);
+    Ok(Page {
+        status,
+        location,
+        content_type,
+        body,
+    })
+}
+
+pub(crate) fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric() && c != '\'')
+        .map(|word| word.trim_matches('\''))
+        .filter(|word| !word.is_empty())
+}
+
+/// Text of the page, without its markup, scripts and styles, as space-separated words
+pub(crate) fn text(html: &str) -> String {
+    static MARKUP: OnceLock<Regex> = OnceLock::new();
+    let markup = MARKUP.get_or_init(|| {
+        Regex::new(r"(?is)<script\b.*?</script>|<style\b.*?</style>|<!--.*?-->|<[^>]*>").unwrap()
+    });
+    words(&markup.replace_all(html, " "))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Sets of three consecutive words of the text of the page, in lower case,
+/// leaving out the given words
+pub(crate) fn shingles(html: &str, ignored: &HashSet<String>) -> HashSet<String> {
+    let text = text(html).to_lowercase();
+    let words: Vec<&str> = words(&text)
+        .filter(|word| !ignored.contains(*word))
+        .collect();
+    words.windows(3).map(|window| window.join(" ")).collect()
+}
+
+/// Jaccard similarity of two sets of shingles, from 0 (nothing in common) to 1
+pub(crate) fn similarity(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f64 / union as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn fetch_truncated() {
+        let mut server = mockito::Server::new_async().await;
+        let client = ClientWithMiddleware::from(reqwest::Client::new());
+        let document = vec![b'%'; MAX_PAGE_SIZE * 2 + 123];
+        server
+            .mock("GET", "/book.pdf")
+            .with_header("Content-Type", "application/pdf")
+            .with_body(&document)
+            .create_async()
+            .await;
+        let url = Url::parse(&format!("{}/book.pdf", server.url())).unwrap();
+        let page = fetch(&client, &url, Endpoint::Target).await.unwrap();
+        assert_eq!(page.body.len(), MAX_PAGE_SIZE);
+        assert_eq!(page.mime_type().as_deref(), Some("application/pdf"));
+        assert!(!page.is_text());
+    }
+}
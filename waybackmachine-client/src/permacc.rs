@@ -0,0 +1,311 @@
+use crate::backend::{ArchiveBackend, CaptureStatus};
+use crate::freshness::{read_json, send_check};
+use crate::ratelimit::{retry_after, Endpoint};
+use crate::{ArchivableUrl, CaptureFailure, Error, Snapshot};
+use async_trait::async_trait;
+use chrono::DateTime;
+use reqwest::header::{HeaderMap, AUTHORIZATION};
+use reqwest::{Response, StatusCode};
+use reqwest_middleware::{ClientWithMiddleware, RequestBuilder};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Endpoint of the Perma.cc API
+pub const PERMA_CC_API_ENDPOINT: &str = "https://api.perma.cc/v1/";
+/// Prefix of Perma.cc replay URLs
+pub const PERMA_CC_REPLAY_ENDPOINT: &str = "https://perma.cc/";
+
+/// Perma.cc, captured through its API with the key of a registered account
+///
+/// https://perma.cc/docs/developer
+#[derive(Clone)]
+pub struct PermaCc {
+    endpoint: String,
+    api_key: String,
+    folder: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ArchiveList {
+    objects: Vec<PermaArchive>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PermaArchive {
+    guid: String,
+    creation_timestamp: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CaptureJob {
+    status: String,
+    message: Option<String>,
+}
+
+impl PermaCc {
+    /// Constructs a Perma.cc backend using the given API key
+    pub fn new(api_key: impl Into<String>) -> Self {
+        PermaCc {
+            endpoint: PERMA_CC_API_ENDPOINT.into(),
+            api_key: api_key.into(),
+            folder: None,
+        }
+    }
+
+    /// Sets the API endpoint
+    pub fn endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Sets the folder new captures are filed in, instead of the account's default one
+    pub fn folder(mut self, folder: u64) -> Self {
+        self.folder = Some(folder);
+        self
+    }
+
+    fn authorized(&self, request: RequestBuilder) -> RequestBuilder {
+        request.header(AUTHORIZATION, format!("ApiKey {}", self.api_key))
+    }
+}
+
+/// Reads why Perma.cc refused or failed to capture the URL
+fn capture_failure(status: StatusCode, body: &str) -> CaptureFailure {
+    match CaptureFailure::parse(status, &HeaderMap::new(), "") {
+        CaptureFailure::Unknown(None) => CaptureFailure::Unknown(error_message(body)),
+        cause => cause,
+    }
+}
+
+/// Finds the error message in a Perma.cc error response, such as
+/// `{"detail": "..."}` or `{"url": ["..."]}`
+fn error_message(body: &str) -> Option<String> {
+    let body: Value = serde_json::from_str(body).ok()?;
+    ["detail", "error", "url"]
+        .iter()
+        .filter_map(|key| match body.get(key)? {
+            Value::String(message) => Some(message.clone()),
+            Value::Array(messages) => messages.first()?.as_str().map(str::to_string),
+            _ => None,
+        })
+        .next()
+}
+
+async fn cannot_archive(to_archive: &ArchivableUrl, response: Response) -> Error {
+    let status = response.status();
+    // a rejected API key is a configuration issue, not a capture failure
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        return Error::ServiceRefused {
+            endpoint: Endpoint::Save,
+            status,
+            retry_after: retry_after(&response),
+        };
+    }
+    let retry_after = retry_after(&response);
+    let body = response.text().await.unwrap_or_default();
+    Error::CannotArchive {
+        url: to_archive.url.to_string(),
+        status,
+        retry_after,
+        cause: capture_failure(status, &body),
+    }
+}
+
+#[async_trait]
+impl ArchiveBackend for PermaCc {
+    fn name(&self) -> &str {
+        "Perma.cc"
+    }
+
+    /// Looks up the most recent archive of the URL made by the account
+    async fn latest_snapshot(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_check: &ArchivableUrl,
+    ) -> Result<Option<Snapshot>, Error> {
+        let url = to_check.without_fragment().to_string();
+        let request = self
+            .authorized(http_client.get(format!("{}archives/", self.endpoint)))
+            .query(&[("url", url.as_str()), ("limit", "1")]);
+        let response: ArchiveList = read_json(send_check(request).await?).await?;
+        match response.objects.into_iter().next() {
+            Some(archive) => Ok(Some(Snapshot {
+                url: format!("{}{}", PERMA_CC_REPLAY_ENDPOINT, archive.guid),
+                timestamp: DateTime::parse_from_rfc3339(&archive.creation_timestamp)?.naive_utc(),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates an archive, whose capture runs in the background
+    async fn request_capture(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_archive: &ArchivableUrl,
+    ) -> Result<CaptureStatus, Error> {
+        let mut archive = json!({ "url": to_archive.without_fragment().as_str() });
+        if let Some(folder) = self.folder {
+            archive["folder"] = folder.into();
+        }
+        let response = self
+            .authorized(http_client.post(format!("{}archives/", self.endpoint)))
+            .json(&archive)
+            .with_extension(Endpoint::Save)
+            .send()
+            .await
+            .map_err(|e| Error::request(Endpoint::Save, e))?;
+        if !response.status().is_success() {
+            return Err(cannot_archive(to_archive, response).await);
+        }
+        let archive: PermaArchive = response.json().await.map_err(|e| {
+            if e.is_decode() {
+                Error::cannot_check("invalid response", e)
+            } else {
+                Error::request(Endpoint::Save, e)
+            }
+        })?;
+        Ok(CaptureStatus::Pending(archive.guid))
+    }
+
+    async fn capture_status(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_archive: &ArchivableUrl,
+        job_id: &str,
+    ) -> Result<CaptureStatus, Error> {
+        let request =
+            self.authorized(http_client.get(format!("{}capture_jobs/{}/", self.endpoint, job_id)));
+        let response = send_check(request).await?;
+        let status = response.status();
+        let capture_job: CaptureJob = read_json(response).await?;
+        match capture_job.status.as_str() {
+            "pending" | "in_progress" => Ok(CaptureStatus::Pending(job_id.to_string())),
+            "completed" => Ok(CaptureStatus::Done(format!(
+                "{}{}",
+                PERMA_CC_REPLAY_ENDPOINT, job_id
+            ))),
+            _ => Err(Error::CannotArchive {
+                url: to_archive.url.to_string(),
+                status,
+                retry_after: None,
+                cause: CaptureFailure::Unknown(capture_job.message),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+
+    fn to_archive() -> ArchivableUrl {
+        ArchivableUrl::parse("https://example.com/page#top").unwrap()
+    }
+
+    #[tokio::test]
+    async fn capture() {
+        let mut server = mockito::Server::new_async().await;
+        let perma = PermaCc::new("secret")
+            .endpoint(format!("{}/v1/", server.url()))
+            .folder(42);
+        let http_client = ClientWithMiddleware::from(reqwest::Client::new());
+
+        let create_mock = server
+            .mock("POST", "/v1/archives/")
+            .match_header("authorization", "ApiKey secret")
+            .match_body(Matcher::Json(
+                json!({"url": "https://example.com/page", "folder": 42}),
+            ))
+            .with_status(201)
+            .with_body(r#"{"guid": "ABCD-1234", "creation_timestamp": "2024-05-01T10:00:00Z"}"#)
+            .create_async()
+            .await;
+        assert_eq!(
+            perma
+                .request_capture(&http_client, &to_archive())
+                .await
+                .unwrap(),
+            CaptureStatus::Pending("ABCD-1234".into())
+        );
+        create_mock.assert_async().await;
+
+        server
+            .mock("GET", "/v1/capture_jobs/ABCD-1234/")
+            .with_status(200)
+            .with_body(r#"{"guid": "ABCD-1234", "status": "completed"}"#)
+            .create_async()
+            .await;
+        assert_eq!(
+            perma
+                .capture_status(&http_client, &to_archive(), "ABCD-1234")
+                .await
+                .unwrap(),
+            CaptureStatus::Done("https://perma.cc/ABCD-1234".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn failures() {
+        let mut server = mockito::Server::new_async().await;
+        let perma = PermaCc::new("secret").endpoint(format!("{}/v1/", server.url()));
+        let http_client = ClientWithMiddleware::from(reqwest::Client::new());
+
+        server
+            .mock("POST", "/v1/archives/")
+            .with_status(400)
+            .with_body(r#"{"url": ["Couldn't resolve domain."]}"#)
+            .create_async()
+            .await;
+        let err = perma
+            .request_capture(&http_client, &to_archive())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.capture_failure(),
+            Some(&CaptureFailure::Unknown(Some(
+                "Couldn't resolve domain.".into()
+            )))
+        );
+
+        server
+            .mock("GET", "/v1/capture_jobs/ABCD-1234/")
+            .with_status(200)
+            .with_body(r#"{"status": "failed", "message": "Target timed out"}"#)
+            .create_async()
+            .await;
+        assert!(matches!(
+            perma
+                .capture_status(&http_client, &to_archive(), "ABCD-1234")
+                .await,
+            Err(Error::CannotArchive { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn latest_snapshot() {
+        let mut server = mockito::Server::new_async().await;
+        let perma = PermaCc::new("secret").endpoint(format!("{}/v1/", server.url()));
+        let http_client = ClientWithMiddleware::from(reqwest::Client::new());
+
+        server
+            .mock("GET", "/v1/archives/")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("url".into(), "https://example.com/page".into()),
+                Matcher::UrlEncoded("limit".into(), "1".into()),
+            ]))
+            .with_status(200)
+            .with_body(
+                r#"{"meta": {}, "objects": [{"guid": "ABCD-1234", "creation_timestamp": "2024-05-01T10:00:00Z"}]}"#,
+            )
+            .create_async()
+            .await;
+        let snapshot = perma
+            .latest_snapshot(&http_client, &to_archive())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.url, "https://perma.cc/ABCD-1234");
+        assert_eq!(snapshot.timestamp.to_string(), "2024-05-01 10:00:00");
+    }
+}
@@ -0,0 +1,316 @@
+use crate::archivableurl::is_subdomain_of;
+use crate::ipaddr::is_public;
+use crate::{ExclusionList, InvalidReason};
+use regex::Regex;
+use std::fmt;
+use std::sync::Arc;
+use url::{Host, Url};
+
+/// Outcome of evaluating an `ArchivePolicy` for a URL
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Decision {
+    /// The policy does not object to archiving the URL
+    Allow,
+    /// The URL is not a valid archiving target, e.g. it uses an unsupported scheme
+    /// or points to a local address
+    Reject(InvalidReason),
+    /// The URL is valid, but must not be archived, e.g. its domain is excluded
+    Exclude(InvalidReason),
+}
+
+/// Rule deciding whether a URL may be archived.
+///
+/// Closures taking a `&Url` and returning a `Decision` implement this trait.
+pub trait ArchivePolicy: Send + Sync {
+    /// Evaluates the policy for the given URL
+    fn check(&self, url: &Url) -> Decision;
+}
+
+impl<F> ArchivePolicy for F
+where
+    F: Fn(&Url) -> Decision + Send + Sync,
+{
+    fn check(&self, url: &Url) -> Decision {
+        self(url)
+    }
+}
+
+/// Only allows URLs with one of the given schemes
+#[derive(Clone, Debug)]
+pub struct SchemePolicy {
+    schemes: Vec<String>,
+}
+
+impl SchemePolicy {
+    /// Allows the given schemes only
+    pub fn new<S: Into<String>>(schemes: impl IntoIterator<Item = S>) -> Self {
+        SchemePolicy {
+            schemes: schemes.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl Default for SchemePolicy {
+    /// Allows HTTP and HTTPS URLs
+    fn default() -> Self {
+        SchemePolicy::new(["http", "https"])
+    }
+}
+
+impl ArchivePolicy for SchemePolicy {
+    fn check(&self, url: &Url) -> Decision {
+        if self.schemes.iter().any(|scheme| scheme == url.scheme()) {
+            Decision::Allow
+        } else {
+            Decision::Reject(InvalidReason::UnsupportedScheme(url.scheme().into()))
+        }
+    }
+}
+
+/// Domains of the local machine, along with their subdomains
+const LOCAL_DOMAINS: [&str; 2] = ["localhost", "localhost.localdomain"];
+
+/// Rejects URLs without a host, or whose host is local or an IP address outside
+/// the public unicast ranges
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HostPolicy;
+
+impl ArchivePolicy for HostPolicy {
+    fn check(&self, url: &Url) -> Decision {
+        match url.host() {
+            None => Decision::Reject(InvalidReason::MissingHost),
+            Some(Host::Domain(domain))
+                if LOCAL_DOMAINS
+                    .iter()
+                    .any(|local| is_subdomain_of(domain, local)) =>
+            {
+                Decision::Reject(InvalidReason::Localhost)
+            }
+            Some(Host::Ipv4(ipv4)) if !is_public(&ipv4.into()) => {
+                Decision::Reject(InvalidReason::PrivateAddress(ipv4.into()))
+            }
+            Some(Host::Ipv6(ipv6)) if !is_public(&ipv6.into()) => {
+                Decision::Reject(InvalidReason::PrivateAddress(ipv6.into()))
+            }
+            _ => Decision::Allow,
+        }
+    }
+}
+
+impl ArchivePolicy for ExclusionList {
+    fn check(&self, url: &Url) -> Decision {
+        match url.domain().and_then(|domain| self.matching_rule(domain)) {
+            Some(rule) => Decision::Exclude(InvalidReason::ExcludedDomain { rule: rule.into() }),
+            None => Decision::Allow,
+        }
+    }
+}
+
+/// Excludes URLs matching any of the given regular expressions
+#[derive(Clone, Debug, Default)]
+pub struct DenyList {
+    patterns: Vec<Regex>,
+}
+
+impl DenyList {
+    /// Excludes URLs matching any of the patterns
+    pub fn new(patterns: impl IntoIterator<Item = Regex>) -> Self {
+        DenyList {
+            patterns: patterns.into_iter().collect(),
+        }
+    }
+}
+
+impl ArchivePolicy for DenyList {
+    fn check(&self, url: &Url) -> Decision {
+        match self
+            .patterns
+            .iter()
+            .find(|pattern| pattern.is_match(url.as_str()))
+        {
+            Some(pattern) => Decision::Exclude(InvalidReason::DeniedPattern {
+                pattern: pattern.to_string(),
+            }),
+            None => Decision::Allow,
+        }
+    }
+}
+
+/// Excludes URLs that do not match any of the given regular expressions
+#[derive(Clone, Debug, Default)]
+pub struct AllowList {
+    patterns: Vec<Regex>,
+}
+
+impl AllowList {
+    /// Only allows URLs matching one of the patterns
+    pub fn new(patterns: impl IntoIterator<Item = Regex>) -> Self {
+        AllowList {
+            patterns: patterns.into_iter().collect(),
+        }
+    }
+}
+
+impl ArchivePolicy for AllowList {
+    fn check(&self, url: &Url) -> Decision {
+        if self
+            .patterns
+            .iter()
+            .any(|pattern| pattern.is_match(url.as_str()))
+        {
+            Decision::Allow
+        } else {
+            Decision::Exclude(InvalidReason::NotAllowed)
+        }
+    }
+}
+
+/// Ordered set of policies. The first one that does not allow a URL decides.
+#[derive(Clone)]
+pub struct Policies {
+    policies: Vec<Arc<dyn ArchivePolicy>>,
+}
+
+impl Policies {
+    /// Constructs a set containing the policies every archived URL must satisfy:
+    /// an HTTP(S) scheme and a public host
+    pub fn new() -> Self {
+        Policies {
+            policies: vec![Arc::new(SchemePolicy::default()), Arc::new(HostPolicy)],
+        }
+    }
+
+    /// Appends a policy, evaluated after the ones already in the set
+    pub fn with(mut self, policy: impl ArchivePolicy + 'static) -> Self {
+        self.policies.push(Arc::new(policy));
+        self
+    }
+}
+
+impl Default for Policies {
+    /// Constructs a set with the required policies and the default `ExclusionList`
+    fn default() -> Self {
+        Policies::new().with(ExclusionList::default())
+    }
+}
+
+impl ArchivePolicy for Policies {
+    fn check(&self, url: &Url) -> Decision {
+        self.policies
+            .iter()
+            .map(|policy| policy.check(url))
+            .find(|decision| *decision != Decision::Allow)
+            .unwrap_or(Decision::Allow)
+    }
+}
+
+impl fmt::Debug for Policies {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Policies({})", self.policies.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn scheme_policy() {
+        let policy = SchemePolicy::default();
+        assert_eq!(policy.check(&url("https://example.com/")), Decision::Allow);
+        assert_eq!(
+            policy.check(&url("ftp://example.com/")),
+            Decision::Reject(InvalidReason::UnsupportedScheme("ftp".into()))
+        );
+    }
+
+    #[test]
+    fn host_policy() {
+        let policy = HostPolicy;
+        assert_eq!(
+            policy.check(&url("https://93.184.216.34/")),
+            Decision::Allow
+        );
+        assert_eq!(
+            policy.check(&url("https://100.64.0.1/")),
+            Decision::Reject(InvalidReason::PrivateAddress("100.64.0.1".parse().unwrap()))
+        );
+        assert_eq!(
+            policy.check(&url("https://[fd00::1]/")),
+            Decision::Reject(InvalidReason::PrivateAddress("fd00::1".parse().unwrap()))
+        );
+        assert_eq!(
+            policy.check(&url("https://[::ffff:192.168.0.1]/")),
+            Decision::Reject(InvalidReason::PrivateAddress(
+                "::ffff:192.168.0.1".parse().unwrap()
+            ))
+        );
+        assert_eq!(
+            policy.check(&url("http://app.localhost/")),
+            Decision::Reject(InvalidReason::Localhost)
+        );
+        // local names are matched label by label
+        assert_eq!(
+            policy.check(&url("https://notlocalhost.org/")),
+            Decision::Allow
+        );
+        assert_eq!(
+            policy.check(&url("https://localhost-press.com/")),
+            Decision::Allow
+        );
+    }
+
+    #[test]
+    fn deny_list() {
+        let policy = DenyList::new([Regex::new(r"^https://example\.com/private/").unwrap()]);
+        assert_eq!(policy.check(&url("https://example.com/")), Decision::Allow);
+        assert_eq!(
+            policy.check(&url("https://example.com/private/page")),
+            Decision::Exclude(InvalidReason::DeniedPattern {
+                pattern: r"^https://example\.com/private/".into()
+            })
+        );
+    }
+
+    #[test]
+    fn allow_list() {
+        let policy = AllowList::new([Regex::new(r"^https://example\.com/").unwrap()]);
+        assert_eq!(policy.check(&url("https://example.com/")), Decision::Allow);
+        assert_eq!(
+            policy.check(&url("https://example.org/")),
+            Decision::Exclude(InvalidReason::NotAllowed)
+        );
+    }
+
+    #[test]
+    fn first_objection_wins() {
+        let policies = Policies::default()
+            .with(|url: &Url| match url.path() {
+                "/draft" => Decision::Exclude(InvalidReason::Other("draft page".into())),
+                _ => Decision::Allow,
+            })
+            .with(|_: &Url| Decision::Exclude(InvalidReason::Other("never reached".into())));
+        assert_eq!(
+            policies.check(&url("ftp://jstor.org/draft")),
+            Decision::Reject(InvalidReason::UnsupportedScheme("ftp".into()))
+        );
+        assert_eq!(
+            policies.check(&url("https://jstor.org/draft")),
+            Decision::Exclude(InvalidReason::ExcludedDomain {
+                rule: "jstor.org".into()
+            })
+        );
+        assert_eq!(
+            policies.check(&url("https://example.com/draft")),
+            Decision::Exclude(InvalidReason::Other("draft page".into()))
+        );
+        assert_eq!(
+            policies.check(&url("https://example.com/")),
+            Decision::Exclude(InvalidReason::Other("never reached".into()))
+        );
+    }
+}
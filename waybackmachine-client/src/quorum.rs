@@ -0,0 +1,56 @@
+use std::fmt;
+
+/// Number of archives that must preserve a URL for it to be considered preserved
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Quorum {
+    /// Every configured archive
+    #[default]
+    All,
+    /// At least this many archives
+    AtLeast(usize),
+}
+
+impl Quorum {
+    /// Returns how many of the given number of archives must succeed
+    pub fn required(&self, archives: usize) -> usize {
+        match self {
+            Quorum::All => archives,
+            Quorum::AtLeast(required) => *required,
+        }
+    }
+
+    /// Checks that the quorum can be reached with the given number of archives
+    pub(crate) fn validate(&self, archives: usize) -> Result<(), String> {
+        match self {
+            Quorum::AtLeast(0) => Err("Quorum must require at least one archive".into()),
+            Quorum::AtLeast(required) if *required > archives => Err(format!(
+                "Quorum of {} cannot be reached with {} archives",
+                required, archives
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Quorum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Quorum::All => write!(f, "all"),
+            Quorum::AtLeast(required) => write!(f, "{}", required),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_archives() {
+        assert_eq!(Quorum::All.required(3), 3);
+        assert_eq!(Quorum::AtLeast(1).required(3), 1);
+        assert!(Quorum::AtLeast(2).validate(2).is_ok());
+        assert!(Quorum::AtLeast(3).validate(2).is_err());
+        assert!(Quorum::AtLeast(0).validate(2).is_err());
+    }
+}
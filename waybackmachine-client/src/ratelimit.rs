@@ -0,0 +1,310 @@
+use chrono::{DateTime, Utc};
+use http::Extensions;
+use reqwest::{header::RETRY_AFTER, Request, Response, StatusCode};
+use reqwest_middleware::{Middleware, Next};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::{sleep_until, Instant};
+
+/// Default number of Save Page Now requests allowed per minute
+const DEFAULT_SAVE_REQUESTS_PER_MINUTE: u32 = 15;
+
+/// Default number of snapshot lookups allowed per minute
+const DEFAULT_CHECK_REQUESTS_PER_MINUTE: u32 = 60;
+
+/// Default number of captures allowed to run at the same time
+const DEFAULT_MAX_CONCURRENT_CAPTURES: usize = 4;
+
+/// Pause applied when the service throttles us without a `Retry-After` header
+const DEFAULT_THROTTLE_PAUSE: Duration = Duration::from_secs(60);
+/// Longest `Retry-After` delay honoured, so that a bogus value cannot stall the run
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Endpoint a request is sent to
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Endpoint {
+    /// URL being archived, requested to follow its redirects. It is not rate limited.
+    Target,
+    /// Save Page Now
+    Save,
+    /// Snapshot lookup (CDX server, Availability API or TimeGate)
+    Check,
+}
+
+/// Client-side limits on the requests sent to the Wayback Machine
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RateLimits {
+    /// Maximum number of Save Page Now requests per minute
+    pub save_requests_per_minute: Option<u32>,
+    /// Maximum number of snapshot lookups per minute
+    pub check_requests_per_minute: Option<u32>,
+    /// Maximum number of captures in flight at the same time
+    pub max_concurrent_captures: Option<usize>,
+}
+
+impl RateLimits {
+    /// No client-side limits. Throttling responses are still honoured.
+    pub fn unlimited() -> Self {
+        RateLimits {
+            save_requests_per_minute: None,
+            check_requests_per_minute: None,
+            max_concurrent_captures: None,
+        }
+    }
+}
+
+impl Default for RateLimits {
+    /// Limits in line with the Save Page Now quotas for anonymous users
+    fn default() -> Self {
+        RateLimits {
+            save_requests_per_minute: Some(DEFAULT_SAVE_REQUESTS_PER_MINUTE),
+            check_requests_per_minute: Some(DEFAULT_CHECK_REQUESTS_PER_MINUTE),
+            max_concurrent_captures: Some(DEFAULT_MAX_CONCURRENT_CAPTURES),
+        }
+    }
+}
+
+/// Request pacing for a single endpoint
+struct Pacer {
+    interval: Option<Duration>,
+    next_slot: Mutex<Instant>,
+}
+
+impl Pacer {
+    fn new(requests_per_minute: Option<u32>) -> Self {
+        Pacer {
+            interval: requests_per_minute
+                .filter(|rpm| *rpm > 0)
+                .map(|rpm| Duration::from_secs(60) / rpm),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves the next free slot and returns the instant at which it starts
+    fn reserve(&self) -> Option<Instant> {
+        let interval = self.interval?;
+        let mut next_slot = self.next_slot.lock().unwrap();
+        let slot = (*next_slot).max(Instant::now());
+        *next_slot = slot + interval;
+        Some(slot)
+    }
+}
+
+/// Rate limiter shared by every request sent by a client.
+///
+/// Requests are spaced evenly to stay within the per-endpoint limits, captures are
+/// capped by a semaphore, and a throttling response (`429`, or `503` with
+/// `Retry-After`) pauses every pending request until the service is willing to
+/// accept them again, instead of each request backing off on its own.
+pub(crate) struct RateLimiter {
+    save: Pacer,
+    check: Pacer,
+    captures: Option<Semaphore>,
+    paused_until: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(rate_limits: &RateLimits) -> Self {
+        RateLimiter {
+            save: Pacer::new(rate_limits.save_requests_per_minute),
+            check: Pacer::new(rate_limits.check_requests_per_minute),
+            captures: rate_limits
+                .max_concurrent_captures
+                .map(|captures| Semaphore::new(captures.max(1))),
+            paused_until: Mutex::new(None),
+        }
+    }
+
+    /// Waits until a request to the given endpoint may be sent
+    async fn wait(&self, endpoint: Endpoint) {
+        self.wait_for_pause().await;
+        let pacer = match endpoint {
+            Endpoint::Target => return,
+            Endpoint::Save => &self.save,
+            Endpoint::Check => &self.check,
+        };
+        if let Some(slot) = pacer.reserve() {
+            sleep_until(slot).await;
+        }
+        // the service may have throttled another request while we were waiting
+        self.wait_for_pause().await;
+    }
+
+    async fn wait_for_pause(&self) {
+        loop {
+            let paused_until = *self.paused_until.lock().unwrap();
+            match paused_until {
+                Some(until) if until > Instant::now() => sleep_until(until).await,
+                _ => return,
+            }
+        }
+    }
+
+    /// Holds every request until `duration` has elapsed
+    fn pause(&self, duration: Duration) {
+        let Some(until) = Instant::now().checked_add(duration) else {
+            return;
+        };
+        let mut paused_until = self.paused_until.lock().unwrap();
+        if paused_until.is_none_or(|current| current < until) {
+            *paused_until = Some(until);
+        }
+    }
+}
+
+/// Parses a `Retry-After` value, given either in seconds or as an HTTP date,
+/// capped at `MAX_RETRY_AFTER`
+pub(crate) fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&Utc) - now)
+                .to_std()
+                .unwrap_or_default()
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+/// Returns how long the service asked us to wait, if the response is a throttling one
+fn throttle_pause(response: &Response) -> Option<Duration> {
+    let retry_after = retry_after(response);
+    match response.status() {
+        StatusCode::TOO_MANY_REQUESTS => Some(retry_after.unwrap_or(DEFAULT_THROTTLE_PAUSE)),
+        StatusCode::SERVICE_UNAVAILABLE => retry_after,
+        _ => None,
+    }
+}
+
+/// Reads the `Retry-After` header of a response, if any
+pub(crate) fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, Utc::now()))
+}
+
+/// Middleware applying a `RateLimiter` to requests tagged with an `Endpoint` extension.
+///
+/// It must be registered after the retry middleware, so that every retry attempt
+/// goes through the limiter.
+pub(crate) struct RateLimitMiddleware {
+    rate_limiter: RateLimiter,
+}
+
+impl RateLimitMiddleware {
+    pub(crate) fn new(rate_limits: &RateLimits) -> Self {
+        RateLimitMiddleware {
+            rate_limiter: RateLimiter::new(rate_limits),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RateLimitMiddleware {
+    async fn handle(
+        &self,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let endpoint = match extensions.get::<Endpoint>() {
+            Some(Endpoint::Target) | None => return next.run(req, extensions).await,
+            Some(endpoint) => *endpoint,
+        };
+        let _permit = match (&self.rate_limiter.captures, endpoint) {
+            (Some(captures), Endpoint::Save) => captures.acquire().await.ok(),
+            _ => None,
+        };
+        self.rate_limiter.wait(endpoint).await;
+
+        let response = next.run(req, extensions).await?;
+        if let Some(pause) = throttle_pause(&response) {
+            self.rate_limiter.pause(pause);
+        }
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(
+            parse_retry_after("120", Utc::now()),
+            Some(Duration::from_secs(120))
+        );
+    }
+
+    #[test]
+    fn retry_after_http_date() {
+        let now = DateTime::parse_from_rfc2822("Wed, 21 Oct 2015 07:28:00 GMT")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:29:30 GMT", now),
+            Some(Duration::from_secs(90))
+        );
+    }
+
+    #[test]
+    fn retry_after_date_in_the_past() {
+        let now = Utc::now();
+        let past = (now - TimeDelta::try_minutes(5).unwrap()).to_rfc2822();
+        assert_eq!(parse_retry_after(&past, now), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn retry_after_capped() {
+        assert_eq!(
+            parse_retry_after("18446744073709551615", Utc::now()),
+            Some(MAX_RETRY_AFTER)
+        );
+        assert_eq!(
+            parse_retry_after("Fri, 31 Dec 9999 23:59:59 GMT", Utc::now()),
+            Some(MAX_RETRY_AFTER)
+        );
+    }
+
+    #[test]
+    fn retry_after_invalid() {
+        assert_eq!(parse_retry_after("soon", Utc::now()), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_are_spaced() {
+        let rate_limiter = RateLimiter::new(&RateLimits {
+            save_requests_per_minute: Some(6),
+            check_requests_per_minute: None,
+            max_concurrent_captures: None,
+        });
+        let start = Instant::now();
+        rate_limiter.wait(Endpoint::Save).await;
+        rate_limiter.wait(Endpoint::Save).await;
+        rate_limiter.wait(Endpoint::Save).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(20));
+        // other endpoints are paced independently
+        rate_limiter.wait(Endpoint::Check).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(20));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pause_holds_every_endpoint() {
+        let rate_limiter = RateLimiter::new(&RateLimits::unlimited());
+        let start = Instant::now();
+        rate_limiter.pause(Duration::from_secs(30));
+        // a shorter pause does not shorten the current one
+        rate_limiter.pause(Duration::from_secs(5));
+        rate_limiter.wait(Endpoint::Check).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+        rate_limiter.wait(Endpoint::Save).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(30));
+    }
+}
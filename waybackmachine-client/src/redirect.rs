@@ -0,0 +1,290 @@
+use crate::ratelimit::Endpoint;
+use crate::resolver::private_address;
+use crate::{Error, InvalidReason, RequestErrorKind};
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::{Response, StatusCode};
+use reqwest_middleware::{ClientWithMiddleware, RequestBuilder};
+use std::fmt;
+use url::Url;
+
+/// Default maximum number of redirects followed from a URL
+pub(crate) const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Which URLs of a redirect chain are checked for recent snapshots and archived
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RedirectStrategy {
+    /// The requested URL, as cited
+    #[default]
+    Original,
+    /// The location the redirects lead to
+    Final,
+    /// Both the requested URL and its final location, if it is redirected
+    Both,
+}
+
+impl RedirectStrategy {
+    /// Returns the URLs of the chain to archive, without duplicates
+    pub fn urls<'a>(&self, redirect_chain: &'a RedirectChain) -> Vec<&'a Url> {
+        match self {
+            RedirectStrategy::Original => vec![redirect_chain.original()],
+            RedirectStrategy::Final => vec![redirect_chain.final_url()],
+            RedirectStrategy::Both if redirect_chain.is_redirected() => {
+                vec![redirect_chain.original(), redirect_chain.final_url()]
+            }
+            RedirectStrategy::Both => vec![redirect_chain.original()],
+        }
+    }
+}
+
+/// How the final location of a redirect chain answered
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TargetStatus {
+    /// The server answered with the given status code
+    Answered(StatusCode),
+    /// No response was received, e.g. because the host no longer resolves
+    Unreachable(RequestErrorKind),
+    /// The location was not requested, as the redirect limit was reached
+    Unchecked,
+}
+
+impl fmt::Display for TargetStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetStatus::Answered(status) => write!(f, "{}", status),
+            TargetStatus::Unreachable(kind) => write!(f, "unreachable, {}", kind),
+            TargetStatus::Unchecked => write!(f, "not checked"),
+        }
+    }
+}
+
+/// URLs visited while following the redirects of a URL, from the requested URL
+/// to its final location, both included
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RedirectChain {
+    urls: Vec<Url>,
+    status: TargetStatus,
+    content_type: Option<String>,
+}
+
+/// Response to a request sent while following redirects
+pub(crate) struct Hop {
+    pub(crate) status: TargetStatus,
+    pub(crate) location: Option<Url>,
+    pub(crate) content_type: Option<String>,
+}
+
+impl RedirectChain {
+    pub(crate) fn new(original: Url) -> Self {
+        RedirectChain {
+            urls: vec![original],
+            status: TargetStatus::Unchecked,
+            content_type: None,
+        }
+    }
+
+    pub(crate) fn push(&mut self, url: Url) {
+        self.urls.push(url);
+        self.status = TargetStatus::Unchecked;
+        self.content_type = None;
+    }
+
+    pub(crate) fn set_status(&mut self, status: TargetStatus) {
+        self.status = status;
+    }
+
+    pub(crate) fn set_content_type(&mut self, content_type: Option<String>) {
+        self.content_type = content_type;
+    }
+
+    /// Returns the requested URL
+    pub fn original(&self) -> &Url {
+        &self.urls[0]
+    }
+
+    /// Returns the location the redirects lead to
+    pub fn final_url(&self) -> &Url {
+        self.urls.last().unwrap()
+    }
+
+    /// Returns every URL of the chain, in the order they were visited
+    pub fn urls(&self) -> &[Url] {
+        &self.urls
+    }
+
+    /// Returns the number of redirects followed
+    pub fn hops(&self) -> usize {
+        self.urls.len() - 1
+    }
+
+    /// Returns `true` if the requested URL redirects elsewhere
+    pub fn is_redirected(&self) -> bool {
+        self.hops() > 0
+    }
+
+    /// Returns how the final location answered
+    pub fn target_status(&self) -> TargetStatus {
+        self.status
+    }
+
+    /// Returns the `Content-Type` the final location answered with, if any
+    pub fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref()
+    }
+
+    /// Returns `true` if the final location is gone: its host no longer resolves
+    /// or refuses connections, or it answers `404 Not Found` or `410 Gone`.
+    ///
+    /// Timeouts, TLS and other failures may be temporary, and are not considered dead.
+    pub fn is_dead(&self) -> bool {
+        match self.status {
+            TargetStatus::Unreachable(kind) => {
+                matches!(kind, RequestErrorKind::Dns | RequestErrorKind::Connect)
+            }
+            TargetStatus::Answered(status) => {
+                status == StatusCode::NOT_FOUND || status == StatusCode::GONE
+            }
+            TargetStatus::Unchecked => false,
+        }
+    }
+
+    /// Returns `true` if the chain already went through the URL
+    pub(crate) fn contains(&self, url: &Url) -> bool {
+        self.urls.contains(url)
+    }
+}
+
+impl fmt::Display for RedirectChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, url) in self.urls.iter().enumerate() {
+            if i > 0 {
+                write!(f, " → ")?;
+            }
+            write!(f, "{}", url)?;
+        }
+        Ok(())
+    }
+}
+
+/// Requests the URL, returning how it answered and where it redirects to, if it does.
+///
+/// A `HEAD` request is sent first, so that the body is not downloaded. Servers
+/// refusing `HEAD` requests, with a client error such as `405 Method Not Allowed`
+/// or with `501 Not Implemented`, are asked again with a `GET` request, whose body
+/// is never read. Other server errors were already retried, and are not asked again.
+///
+/// Host names that only resolve to private addresses fail the visit, if a
+/// `Resolver` is configured.
+pub(crate) async fn visit(client: &ClientWithMiddleware, url: &Url) -> Result<Hop, Error> {
+    let unreachable = |kind| Hop {
+        status: TargetStatus::Unreachable(kind),
+        location: None,
+        content_type: None,
+    };
+    let mut response = match send(client.head(url.clone()), url).await? {
+        Ok(response) => response,
+        Err(kind) => return Ok(unreachable(kind)),
+    };
+    if response.status().is_client_error() || response.status() == StatusCode::NOT_IMPLEMENTED {
+        response = match send(client.get(url.clone()), url).await? {
+            Ok(response) => response,
+            Err(kind) => return Ok(unreachable(kind)),
+        };
+    }
+    let location = response
+        .status()
+        .is_redirection()
+        .then(|| response.headers().get(LOCATION)?.to_str().ok())
+        .flatten()
+        .and_then(|location| url.join(location).ok());
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .map(str::to_string);
+    Ok(Hop {
+        status: TargetStatus::Answered(response.status()),
+        location,
+        content_type,
+    })
+}
+
+async fn send(
+    request: RequestBuilder,
+    url: &Url,
+) -> Result<Result<Response, RequestErrorKind>, Error> {
+    match request.with_extension(Endpoint::Target).send().await {
+        Ok(response) => Ok(Ok(response)),
+        Err(e) => match private_address(&e) {
+            Some(addr) => Err(Error::InvalidUrl(
+                url.to_string(),
+                InvalidReason::ResolvesToPrivateAddress(addr),
+            )),
+            None => Ok(Err(RequestErrorKind::of(&e))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn display_chain() {
+        let mut chain = RedirectChain::new(url("https://doi.org/10.11647/obp.0001"));
+        assert_eq!(chain.to_string(), "https://doi.org/10.11647/obp.0001");
+        chain.push(url(
+            "https://www.openbookpublishers.com/books/10.11647/obp.0001",
+        ));
+        assert_eq!(
+            chain.to_string(),
+            "https://doi.org/10.11647/obp.0001 → https://www.openbookpublishers.com/books/10.11647/obp.0001"
+        );
+        assert_eq!(chain.hops(), 1);
+        assert_eq!(
+            chain.original().as_str(),
+            "https://doi.org/10.11647/obp.0001"
+        );
+    }
+
+    #[test]
+    fn dead_target() {
+        let mut chain = RedirectChain::new(url("https://example.com/gone"));
+        assert!(!chain.is_dead());
+        chain.set_status(TargetStatus::Answered(StatusCode::GONE));
+        assert!(chain.is_dead());
+        chain.set_status(TargetStatus::Answered(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!chain.is_dead());
+        chain.set_status(TargetStatus::Unreachable(RequestErrorKind::Dns));
+        assert!(chain.is_dead());
+        chain.set_status(TargetStatus::Unreachable(RequestErrorKind::Timeout));
+        assert!(!chain.is_dead());
+        chain.set_status(TargetStatus::Unreachable(RequestErrorKind::Tls));
+        assert!(!chain.is_dead());
+    }
+
+    #[test]
+    fn strategy_urls() {
+        let mut chain = RedirectChain::new(url("https://bit.ly/abc"));
+        assert_eq!(
+            RedirectStrategy::Both.urls(&chain),
+            vec![&url("https://bit.ly/abc")]
+        );
+        chain.push(url("https://example.com/"));
+        assert_eq!(
+            RedirectStrategy::Original.urls(&chain),
+            vec![&url("https://bit.ly/abc")]
+        );
+        assert_eq!(
+            RedirectStrategy::Final.urls(&chain),
+            vec![&url("https://example.com/")]
+        );
+        assert_eq!(
+            RedirectStrategy::Both.urls(&chain),
+            vec![&url("https://bit.ly/abc"), &url("https://example.com/")]
+        );
+    }
+}
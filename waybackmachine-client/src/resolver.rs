@@ -0,0 +1,182 @@
+use crate::ipaddr::is_public;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+/// Resolves host names to IP addresses, so that URLs pointing to a private network
+/// through DNS can be rejected when they are requested.
+#[async_trait::async_trait]
+pub trait Resolver: Send + Sync {
+    /// Returns the addresses the host name resolves to
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>>;
+}
+
+/// Resolver using the system's name resolution
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+#[async_trait::async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+        let addrs = tokio::net::lookup_host((host, 0)).await?;
+        Ok(addrs.map(|addr| addr.ip()).collect())
+    }
+}
+
+/// Host name resolution of the HTTP clients, through a `Resolver`.
+///
+/// Failures are reported as `ResolveError`s, so that they can be told apart from
+/// other connection errors. With `public_only`, non-public addresses are left out
+/// when connecting, so that a host name cannot resolve to a public address when
+/// checked and to a private one when requested.
+pub(crate) struct HostResolver {
+    resolver: Arc<dyn Resolver>,
+    public_only: bool,
+}
+
+impl HostResolver {
+    /// Resolves with the system's name resolution, keeping every address
+    pub(crate) fn system() -> Self {
+        HostResolver {
+            resolver: Arc::new(SystemResolver),
+            public_only: false,
+        }
+    }
+
+    /// Resolves with the given resolver, keeping only public addresses
+    pub(crate) fn public_only(resolver: Arc<dyn Resolver>) -> Self {
+        HostResolver {
+            resolver,
+            public_only: true,
+        }
+    }
+}
+
+impl Resolve for HostResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let resolver = self.resolver.clone();
+        let public_only = self.public_only;
+        Box::pin(async move {
+            let (public, private): (Vec<IpAddr>, Vec<IpAddr>) = resolver
+                .resolve(name.as_str())
+                .await
+                .map_err(ResolveError)?
+                .into_iter()
+                .partition(|addr| !public_only || is_public(addr));
+            match private.first() {
+                Some(addr) if public.is_empty() => Err(PrivateAddress(*addr).into()),
+                _ => {
+                    let addrs: Addrs =
+                        Box::new(public.into_iter().map(|addr| SocketAddr::new(addr, 0)));
+                    Ok(addrs)
+                }
+            }
+        })
+    }
+}
+
+/// Error of a host name that could not be resolved
+#[derive(Debug)]
+pub(crate) struct ResolveError(io::Error);
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot resolve host name: {}", self.0)
+    }
+}
+
+impl StdError for ResolveError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.0)
+    }
+}
+
+/// Error of a host name resolving only to non-public addresses
+#[derive(Debug)]
+pub(crate) struct PrivateAddress(pub(crate) IpAddr);
+
+impl fmt::Display for PrivateAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "resolves to private address {}", self.0)
+    }
+}
+
+impl StdError for PrivateAddress {}
+
+/// Returns the address a request was refused to connect to, if any
+pub(crate) fn private_address(err: &reqwest_middleware::Error) -> Option<IpAddr> {
+    let mut source: Option<&(dyn StdError + 'static)> = Some(err);
+    while let Some(cause) = source {
+        if let Some(PrivateAddress(addr)) = cause.downcast_ref() {
+            return Some(*addr);
+        }
+        source = cause.source();
+    }
+    None
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// Resolver answering from a fixed table
+    pub(crate) struct StaticResolver(pub HashMap<String, Vec<IpAddr>>);
+
+    impl StaticResolver {
+        pub(crate) fn new(entries: &[(&str, &str)]) -> Self {
+            let mut table: HashMap<String, Vec<IpAddr>> = HashMap::new();
+            for (host, ip) in entries {
+                table
+                    .entry(host.to_string())
+                    .or_default()
+                    .push(ip.parse().unwrap());
+            }
+            StaticResolver(table)
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Resolver for StaticResolver {
+        async fn resolve(&self, host: &str) -> io::Result<Vec<IpAddr>> {
+            self.0
+                .get(host)
+                .cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, host.to_string()))
+        }
+    }
+
+    async fn resolve(resolver: &HostResolver, host: &str) -> Result<Vec<IpAddr>, String> {
+        match resolver.resolve(host.parse().unwrap()).await {
+            Ok(addrs) => Ok(addrs.map(|addr| addr.ip()).collect()),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn public_addresses() {
+        let resolver = HostResolver::public_only(Arc::new(StaticResolver::new(&[
+            ("public.example.com", "93.184.216.34"),
+            ("internal.example.com", "10.0.0.1"),
+            ("mixed.example.com", "93.184.216.34"),
+            ("mixed.example.com", "fd00::1"),
+        ])));
+        assert_eq!(
+            resolve(&resolver, "public.example.com").await,
+            Ok(vec!["93.184.216.34".parse().unwrap()])
+        );
+        assert_eq!(
+            resolve(&resolver, "internal.example.com").await,
+            Err("resolves to private address 10.0.0.1".into())
+        );
+        // only the public addresses are connected to
+        assert_eq!(
+            resolve(&resolver, "mixed.example.com").await,
+            Ok(vec!["93.184.216.34".parse().unwrap()])
+        );
+        assert!(resolve(&resolver, "unknown.example.com").await.is_err());
+    }
+}
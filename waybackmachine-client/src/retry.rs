@@ -0,0 +1,278 @@
+use crate::ratelimit::Endpoint;
+use crate::resolver::private_address;
+use chrono::{DateTime, Utc};
+use http::Extensions;
+use reqwest::{Request, Response, StatusCode};
+use reqwest_middleware::{Middleware, Next};
+use reqwest_retry::policies::ExponentialBackoff;
+use reqwest_retry::{
+    default_on_request_failure, default_on_request_success, RetryTransientMiddleware, Retryable,
+    RetryableStrategy,
+};
+use retry_policies::{RetryDecision, RetryPolicy};
+use std::time::Duration;
+
+/// Maximum number of allowed request retries attempts.
+const DEFAULT_MAX_RETRIES: u32 = 10;
+
+/// Shortest wait before retrying a request
+const DEFAULT_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest wait before retrying a request
+const DEFAULT_MAX_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Randomisation applied to the wait between retries, so that concurrent
+/// requests failing together do not retry together
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Jitter {
+    /// Always wait the computed backoff
+    None,
+    /// Wait between zero and the computed backoff
+    #[default]
+    Full,
+    /// Wait between the minimum interval and the computed backoff
+    Bounded,
+}
+
+/// How failed requests of one kind are retried, with an exponential backoff.
+///
+/// A request is retried until `max_retries` or `max_total_duration` is reached,
+/// whichever comes first.
+#[derive(Clone, Debug)]
+pub struct RetrySettings {
+    /// Maximum number of retries after the first attempt
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each following one
+    pub min_interval: Duration,
+    /// Upper bound of the wait between two attempts
+    pub max_interval: Duration,
+    /// Time after the first attempt past which no retry is scheduled
+    pub max_total_duration: Option<Duration>,
+    /// Randomisation of the wait between two attempts
+    pub jitter: Jitter,
+    /// Statuses worth retrying. When unset, server errors, `408 Request Timeout`
+    /// and `429 Too Many Requests` are retried.
+    pub retryable_statuses: Option<Vec<StatusCode>>,
+    /// Timeout of each attempt, from connection until the body is read
+    pub timeout: Option<Duration>,
+}
+
+impl RetrySettings {
+    /// Settings sending each request only once
+    pub fn none() -> Self {
+        RetrySettings {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Checks that the settings can be applied
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.min_interval > self.max_interval {
+            return Err(format!(
+                "Minimum retry interval {:?} is greater than the maximum {:?}",
+                self.min_interval, self.max_interval
+            ));
+        }
+        Ok(())
+    }
+
+    fn backoff(&self) -> Backoff {
+        let jitter = match self.jitter {
+            Jitter::None => retry_policies::Jitter::None,
+            Jitter::Full => retry_policies::Jitter::Full,
+            Jitter::Bounded => retry_policies::Jitter::Bounded,
+        };
+        Backoff {
+            backoff: ExponentialBackoff::builder()
+                .retry_bounds(self.min_interval, self.max_interval)
+                .jitter(jitter)
+                .build_with_max_retries(self.max_retries),
+            max_total_duration: self.max_total_duration,
+        }
+    }
+
+    fn middleware(&self) -> RetryTransientMiddleware<Backoff, StatusStrategy> {
+        RetryTransientMiddleware::new_with_policy_and_strategy(
+            self.backoff(),
+            StatusStrategy {
+                retryable_statuses: self.retryable_statuses.clone(),
+            },
+        )
+    }
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        RetrySettings {
+            max_retries: DEFAULT_MAX_RETRIES,
+            min_interval: DEFAULT_MIN_INTERVAL,
+            max_interval: DEFAULT_MAX_INTERVAL,
+            max_total_duration: None,
+            jitter: Jitter::default(),
+            retryable_statuses: None,
+            timeout: None,
+        }
+    }
+}
+
+/// Exponential backoff giving up once retrying would exceed the total duration
+struct Backoff {
+    backoff: ExponentialBackoff,
+    max_total_duration: Option<Duration>,
+}
+
+impl RetryPolicy for Backoff {
+    fn should_retry(
+        &self,
+        request_start_time: DateTime<Utc>,
+        n_past_retries: u32,
+    ) -> RetryDecision {
+        let decision = self
+            .backoff
+            .should_retry(request_start_time, n_past_retries);
+        let deadline = self.max_total_duration.and_then(|max_total_duration| {
+            chrono::TimeDelta::from_std(max_total_duration)
+                .ok()
+                .and_then(|max_total_duration| {
+                    request_start_time.checked_add_signed(max_total_duration)
+                })
+        });
+        match (decision, deadline) {
+            (RetryDecision::Retry { execute_after }, Some(deadline))
+                if execute_after > deadline =>
+            {
+                RetryDecision::DoNotRetry
+            }
+            (decision, _) => decision,
+        }
+    }
+}
+
+/// Retries the configured statuses, or the default transient ones
+struct StatusStrategy {
+    retryable_statuses: Option<Vec<StatusCode>>,
+}
+
+impl RetryableStrategy for StatusStrategy {
+    fn handle(&self, res: &Result<Response, reqwest_middleware::Error>) -> Option<Retryable> {
+        match (res, &self.retryable_statuses) {
+            (Ok(response), _) if response.status().is_success() => None,
+            (Ok(response), Some(statuses)) if statuses.contains(&response.status()) => {
+                Some(Retryable::Transient)
+            }
+            (Ok(_), Some(_)) => Some(Retryable::Fatal),
+            (Ok(response), None) => default_on_request_success(response),
+            // a host name resolving to a private address will keep doing so
+            (Err(err), _) if private_address(err).is_some() => Some(Retryable::Fatal),
+            (Err(err), _) => default_on_request_failure(err),
+        }
+    }
+}
+
+/// Middleware retrying each request with the settings of the `Endpoint` it is
+/// tagged with. Untagged requests are considered `Endpoint::Target` requests.
+pub(crate) struct RetryMiddleware {
+    target: EndpointRetry,
+    check: EndpointRetry,
+    save: EndpointRetry,
+}
+
+struct EndpointRetry {
+    retry: RetryTransientMiddleware<Backoff, StatusStrategy>,
+    timeout: Option<Duration>,
+}
+
+impl EndpointRetry {
+    fn new(settings: &RetrySettings) -> Self {
+        EndpointRetry {
+            retry: settings.middleware(),
+            timeout: settings.timeout,
+        }
+    }
+}
+
+impl RetryMiddleware {
+    pub(crate) fn new(target: &RetrySettings, check: &RetrySettings, save: &RetrySettings) -> Self {
+        RetryMiddleware {
+            target: EndpointRetry::new(target),
+            check: EndpointRetry::new(check),
+            save: EndpointRetry::new(save),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for RetryMiddleware {
+    async fn handle(
+        &self,
+        mut req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let endpoint_retry = match extensions.get::<Endpoint>() {
+            Some(Endpoint::Save) => &self.save,
+            Some(Endpoint::Check) => &self.check,
+            Some(Endpoint::Target) | None => &self.target,
+        };
+        if let Some(timeout) = endpoint_retry.timeout {
+            req.timeout_mut().get_or_insert(timeout);
+        }
+        endpoint_retry.retry.handle(req, extensions, next).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backoff(max_total_duration: Option<Duration>) -> Backoff {
+        RetrySettings {
+            max_retries: 5,
+            min_interval: Duration::from_secs(10),
+            max_interval: Duration::from_secs(60),
+            max_total_duration,
+            jitter: Jitter::None,
+            ..Default::default()
+        }
+        .backoff()
+    }
+
+    #[test]
+    fn max_retries() {
+        let backoff = backoff(None);
+        assert!(matches!(
+            backoff.should_retry(Utc::now(), 4),
+            RetryDecision::Retry { .. }
+        ));
+        assert!(matches!(
+            backoff.should_retry(Utc::now(), 5),
+            RetryDecision::DoNotRetry
+        ));
+    }
+
+    #[test]
+    fn max_total_duration() {
+        let backoff = backoff(Some(Duration::from_secs(30)));
+        // the first retry is 10s after the start, the third one 40s after
+        assert!(matches!(
+            backoff.should_retry(Utc::now(), 0),
+            RetryDecision::Retry { .. }
+        ));
+        assert!(matches!(
+            backoff.should_retry(Utc::now(), 2),
+            RetryDecision::DoNotRetry
+        ));
+    }
+
+    #[test]
+    fn invalid_bounds() {
+        let settings = RetrySettings {
+            min_interval: Duration::from_secs(60),
+            max_interval: Duration::from_secs(1),
+            ..Default::default()
+        };
+        assert!(settings.validate().is_err());
+        assert!(RetrySettings::default().validate().is_ok());
+    }
+}
@@ -0,0 +1,340 @@
+use crate::archivableurl::is_subdomain_of;
+use crate::Error;
+use chrono::TimeDelta;
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use url::Url;
+
+/// How long an existing snapshot of a URL is good enough
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Freshness {
+    /// Re-archive once the latest snapshot is older than the given age
+    MaxAge(TimeDelta),
+    /// Do not re-archive if a snapshot was taken within the given time of the
+    /// reference date of the request (e.g. the publication date of the citing
+    /// document), before or after it. Requests without a reference date are
+    /// treated as `MaxAge`.
+    NearReference(TimeDelta),
+    /// Never re-archive if any snapshot exists
+    AnySnapshot,
+}
+
+/// URLs a `FreshnessRule` applies to
+#[derive(Clone, Debug)]
+pub enum RuleMatcher {
+    /// The given domain and all its subdomains
+    Host(String),
+    /// URLs matching the regular expression
+    Regex(Regex),
+}
+
+/// Freshness applying to the URLs matched by a `RuleMatcher`
+#[derive(Clone, Debug)]
+pub struct FreshnessRule {
+    pub matcher: RuleMatcher,
+    pub freshness: Freshness,
+}
+
+/// Ordered table of per-domain freshness rules. The first matching rule wins.
+///
+/// Rules can be loaded from a TOML file:
+///
+/// ```toml
+/// [[rule]]
+/// host = "gov.uk"
+/// threshold = "never"
+///
+/// [[rule]]
+/// regex = "^https://www\\.bbc\\.co\\.uk/news/"
+/// threshold = "1h"
+///
+/// [[rule]]
+/// host = "doi.org"
+/// threshold = "6mo"
+/// relative_to = "publication"
+/// ```
+///
+/// Thresholds are given as a number followed by a unit (`s`, `m`, `h`, `d`, `w`,
+/// `mo` for 30 days or `y` for 365 days), or `never` to never re-archive a URL that
+/// already has a snapshot. They are counted back from now, or with
+/// `relative_to = "publication"`, on either side of the publication date.
+#[derive(Clone, Debug, Default)]
+pub struct FreshnessRules {
+    rules: Vec<FreshnessRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    host: Option<String>,
+    regex: Option<String>,
+    threshold: String,
+    relative_to: Option<String>,
+}
+
+impl FreshnessRule {
+    /// Checks if the rule applies to the given URL
+    pub fn matches(&self, url: &Url) -> bool {
+        match &self.matcher {
+            RuleMatcher::Host(domain) => url
+                .host_str()
+                .is_some_and(|host| is_subdomain_of(host, domain)),
+            RuleMatcher::Regex(regex) => regex.is_match(url.as_str()),
+        }
+    }
+}
+
+impl FreshnessRules {
+    /// Constructs an empty rules table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a rule for a domain and its subdomains
+    pub fn host(mut self, domain: impl Into<String>, freshness: Freshness) -> Self {
+        self.rules.push(FreshnessRule {
+            matcher: RuleMatcher::Host(domain.into()),
+            freshness,
+        });
+        self
+    }
+
+    /// Appends a rule for URLs matching a regular expression
+    pub fn regex(mut self, regex: Regex, freshness: Freshness) -> Self {
+        self.rules.push(FreshnessRule {
+            matcher: RuleMatcher::Regex(regex),
+            freshness,
+        });
+        self
+    }
+
+    /// Returns the freshness of the first rule matching the URL, if any
+    pub fn lookup(&self, url: &Url) -> Option<Freshness> {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(url))
+            .map(|rule| rule.freshness)
+    }
+
+    /// Returns `true` if there are no rules
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Parses a rules table in TOML format
+    pub fn from_toml(input: &str) -> Result<Self, Error> {
+        let rules_file: RulesFile = toml::from_str(input)
+            .map_err(|e| Error::InvalidConfig(format!("Invalid freshness rules: {}", e)))?;
+        let mut rules = FreshnessRules::new();
+        for entry in rules_file.rule {
+            let freshness = match (
+                parse_freshness(&entry.threshold)?,
+                entry.relative_to.as_deref(),
+            ) {
+                (freshness, None | Some("now")) => freshness,
+                (Freshness::MaxAge(window), Some("publication")) => {
+                    Freshness::NearReference(window)
+                }
+                (freshness, Some("publication")) => freshness,
+                (_, Some(relative_to)) => {
+                    return Err(Error::InvalidConfig(format!(
+                        "Invalid freshness reference: {}",
+                        relative_to
+                    )))
+                }
+            };
+            rules = match (entry.host, entry.regex) {
+                (Some(host), None) => rules.host(host, freshness),
+                (None, Some(regex)) => {
+                    let regex = Regex::new(&regex).map_err(|e| {
+                        Error::InvalidConfig(format!("Invalid freshness rule regex: {}", e))
+                    })?;
+                    rules.regex(regex, freshness)
+                }
+                _ => {
+                    return Err(Error::InvalidConfig(
+                        "Freshness rules need exactly one of `host` or `regex`".into(),
+                    ))
+                }
+            };
+        }
+        Ok(rules)
+    }
+
+    /// Reads a rules table from a TOML file
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let input = fs::read_to_string(path)
+            .map_err(|e| Error::InvalidConfig(format!("Cannot read {}: {}", path.display(), e)))?;
+        Self::from_toml(&input)
+    }
+}
+
+impl FromStr for Freshness {
+    type Err = Error;
+
+    /// Parses a threshold such as `30d`, `12h`, `6mo` or `never`
+    fn from_str(threshold: &str) -> Result<Self, Error> {
+        parse_freshness(threshold)
+    }
+}
+
+/// Parses a threshold such as `30d`, `12h`, `6mo` or `never`
+fn parse_freshness(threshold: &str) -> Result<Freshness, Error> {
+    let threshold = threshold.trim();
+    if threshold == "never" {
+        return Ok(Freshness::AnySnapshot);
+    }
+    let invalid = || Error::InvalidConfig(format!("Invalid freshness threshold: {}", threshold));
+    let unit_start = threshold
+        .find(|c: char| c.is_alphabetic())
+        .unwrap_or(threshold.len());
+    let (amount, unit) = threshold.split_at(unit_start);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let max_age = match unit {
+        "s" => TimeDelta::try_seconds(amount),
+        "m" => TimeDelta::try_minutes(amount),
+        "h" => TimeDelta::try_hours(amount),
+        "d" => TimeDelta::try_days(amount),
+        "w" => TimeDelta::try_weeks(amount),
+        "mo" => amount.checked_mul(30).and_then(TimeDelta::try_days),
+        "y" => amount.checked_mul(365).and_then(TimeDelta::try_days),
+        _ => None,
+    }
+    .filter(|max_age| *max_age >= TimeDelta::zero())
+    .ok_or_else(invalid)?;
+    Ok(Freshness::MaxAge(max_age))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: &str = r#"
+        [[rule]]
+        host = "gov.uk"
+        threshold = "never"
+
+        [[rule]]
+        regex = "^https://www\\.bbc\\.co\\.uk/news/"
+        threshold = "1h"
+
+        [[rule]]
+        host = "bbc.co.uk"
+        threshold = "2w"
+    "#;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn lookup_rules() {
+        let rules = FreshnessRules::from_toml(RULES).unwrap();
+        assert_eq!(
+            rules.lookup(&url("https://www.gov.uk/guidance")),
+            Some(Freshness::AnySnapshot)
+        );
+        assert_eq!(
+            rules.lookup(&url("https://www.bbc.co.uk/news/world")),
+            Some(Freshness::MaxAge(TimeDelta::try_hours(1).unwrap()))
+        );
+        assert_eq!(
+            rules.lookup(&url("https://www.bbc.co.uk/sport")),
+            Some(Freshness::MaxAge(TimeDelta::try_weeks(2).unwrap()))
+        );
+        assert_eq!(rules.lookup(&url("https://example.com/")), None);
+    }
+
+    #[test]
+    fn host_rules_match_whole_labels() {
+        let rules = FreshnessRules::new().host("gov.uk", Freshness::AnySnapshot);
+        assert!(rules.lookup(&url("https://gov.uk/")).is_some());
+        assert!(rules.lookup(&url("https://notgov.uk/")).is_none());
+    }
+
+    #[test]
+    fn parse_thresholds() {
+        assert_eq!(
+            parse_freshness("30d").unwrap(),
+            Freshness::MaxAge(TimeDelta::try_days(30).unwrap())
+        );
+        assert_eq!(
+            parse_freshness("90s").unwrap(),
+            Freshness::MaxAge(TimeDelta::try_seconds(90).unwrap())
+        );
+        assert_eq!(
+            parse_freshness("6mo").unwrap(),
+            Freshness::MaxAge(TimeDelta::try_days(180).unwrap())
+        );
+        assert!(parse_freshness("30").is_err());
+        assert!(parse_freshness("-1d").is_err());
+        assert!(parse_freshness("d").is_err());
+        assert!(parse_freshness("").is_err());
+        assert!(parse_freshness("1é").is_err());
+    }
+
+    #[test]
+    fn relative_to_publication() {
+        let rules = FreshnessRules::from_toml(
+            r#"
+            [[rule]]
+            host = "doi.org"
+            threshold = "6mo"
+            relative_to = "publication"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.lookup(&url("https://doi.org/10.11647/obp.0001")),
+            Some(Freshness::NearReference(TimeDelta::try_days(180).unwrap()))
+        );
+        assert!(FreshnessRules::from_toml(
+            r#"
+            [[rule]]
+            host = "doi.org"
+            threshold = "6mo"
+            relative_to = "tomorrow"
+            "#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(FreshnessRules::from_toml(
+            r#"
+            [[rule]]
+            threshold = "1d"
+            "#
+        )
+        .is_err());
+        assert!(FreshnessRules::from_toml(
+            r#"
+            [[rule]]
+            host = "example.com"
+            regex = "example"
+            threshold = "1d"
+            "#
+        )
+        .is_err());
+        assert!(FreshnessRules::from_toml(
+            r#"
+            [[rule]]
+            regex = "("
+            threshold = "1d"
+            "#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,33 @@
+use chrono::NaiveDateTime;
+use std::fmt;
+
+/// Format of the 14-digit timestamps used by the Wayback Machine
+pub const WAYBACK_TIMESTAMP_FORMAT: &str = "%Y%m%d%H%M%S";
+
+/// An existing capture of a URL in an archive
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot {
+    /// URL where the capture can be replayed
+    pub url: String,
+    /// Time (UTC) at which the capture was taken
+    pub timestamp: NaiveDateTime,
+}
+
+impl Snapshot {
+    /// Constructs a `Snapshot` from a Wayback Machine timestamp
+    pub fn from_wayback_timestamp(
+        url: String,
+        timestamp: &str,
+    ) -> Result<Self, chrono::ParseError> {
+        Ok(Snapshot {
+            url,
+            timestamp: NaiveDateTime::parse_from_str(timestamp, WAYBACK_TIMESTAMP_FORMAT)?,
+        })
+    }
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
@@ -0,0 +1,299 @@
+use crate::page::{self, fetch, shingles, similarity, text, words};
+use crate::ratelimit::Endpoint;
+use crate::redirect::{RedirectChain, TargetStatus};
+use regex::Regex;
+use reqwest_middleware::ClientWithMiddleware;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::OnceLock;
+use url::Url;
+
+/// Similarity above which a page is considered the same as the missing sibling page
+const SAME_PAGE_SIMILARITY: f64 = 0.9;
+
+/// Titles of error pages
+const ERROR_TITLE_PATTERN: &str = r"(?i)\b(404|not found|page missing|no longer (exists|available)|does ?n[o']t exist|(can ?not|can't|could not) be found)\b";
+/// Sentences of error pages, specific enough not to be found in the content of a page
+const ERROR_BODY_PATTERN: &str = r"(?i)\b(page not found|404 not found|error 404|(page|content|article) (that )?you('re| are)? (were )?(looking for|requested) (could not|cannot|can't|does not|doesn't|no longer|was not|wasn't|is no longer)|this page (no longer exists|does not exist|doesn't exist|has been removed))\b";
+
+/// Whether URLs answering successfully are checked for soft-404s, and what is
+/// done with them
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Soft404Detection {
+    /// Pages are archived without being checked
+    #[default]
+    Off,
+    /// Likely soft-404s are reported in the `ArchiveReport`, and archived anyway
+    Report,
+    /// Likely soft-404s are not archived: `Error::LikelySoft404` is returned instead
+    Skip,
+}
+
+/// Why the final location of a URL looks like a missing page, although it
+/// answers successfully
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Soft404 {
+    /// The URL redirects to the root of a site
+    RedirectToHomepage,
+    /// The title or the text of the page reads like an error message, quoted
+    ErrorMessage(String),
+    /// A made-up path next to the URL leads to the same page
+    SameAsMissingPage,
+}
+
+impl fmt::Display for Soft404 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Soft404::RedirectToHomepage => write!(f, "redirects to the homepage"),
+            Soft404::ErrorMessage(message) => write!(f, "error message \"{}\"", message),
+            Soft404::SameAsMissingPage => write!(f, "same page as a missing path"),
+        }
+    }
+}
+
+/// Looks for signs that the final location of a redirect chain answering
+/// successfully is in fact a missing page.
+///
+/// Redirects to the root of a site are spotted without any request. Documents that
+/// are not text, such as PDFs, are not downloaded and never considered soft-404s.
+/// Otherwise the page is downloaded and its title and text matched against common error messages.
+/// Finally a random path next to it is requested: if it leads to the same page,
+/// the site answers every missing path with that page.
+pub(crate) async fn detect(
+    client: &ClientWithMiddleware,
+    redirect_chain: &RedirectChain,
+) -> Option<Soft404> {
+    match redirect_chain.target_status() {
+        TargetStatus::Answered(status) if status.is_success() => {}
+        _ => return None,
+    }
+    let target = redirect_chain.final_url();
+    if is_homepage(target) {
+        return (!is_homepage(redirect_chain.original())).then_some(Soft404::RedirectToHomepage);
+    }
+
+    let mime_type = redirect_chain.content_type().and_then(page::mime_type);
+    if mime_type.is_some_and(|mime_type| !page::is_text(&mime_type)) {
+        return None;
+    }
+
+    let page = fetch(client, target, Endpoint::Target).await.ok()?;
+    if !page.is_text() {
+        return None;
+    }
+    if let Some(message) = error_message(&page.html()) {
+        return Some(Soft404::ErrorMessage(message));
+    }
+
+    let token = uuid::Uuid::new_v4().simple().to_string();
+    let missing = fetch(client, &sibling(target, &token), Endpoint::Target)
+        .await
+        .ok()?;
+    if missing.status.is_redirection() {
+        // catch-all redirects of missing paths to the page the URL redirects to
+        let redirected_to = redirect_chain.urls().get(1);
+        return (redirect_chain.is_redirected()
+            && missing.location.as_ref().map(without_query) == redirected_to.map(without_query))
+        .then_some(Soft404::SameAsMissingPage);
+    }
+    if !missing.status.is_success() {
+        return None;
+    }
+    // missing pages often quote the requested path
+    let mut echoed = path_words(target);
+    echoed.insert(token);
+    let page_shingles = shingles(&page.html(), &echoed);
+    (!page_shingles.is_empty()
+        && similarity(&page_shingles, &shingles(&missing.html(), &echoed)) >= SAME_PAGE_SIMILARITY)
+        .then_some(Soft404::SameAsMissingPage)
+}
+
+fn is_homepage(url: &Url) -> bool {
+    url.path() == "/" && url.query().is_none()
+}
+
+fn without_query(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_query(None);
+    url.set_fragment(None);
+    url
+}
+
+/// Replaces the last segment of the path with the token, keeping any trailing slash
+fn sibling(url: &Url, token: &str) -> Url {
+    let mut sibling = without_query(url);
+    if let Ok(mut segments) = sibling.path_segments_mut() {
+        if url.path().ends_with('/') {
+            segments.pop().pop().push(token).push("");
+        } else {
+            segments.pop().push(token);
+        }
+    }
+    sibling
+}
+
+fn path_words(url: &Url) -> HashSet<String> {
+    words(&url.path().to_lowercase())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the title of the page, or the sentence of its text, matching an error message
+pub(crate) fn error_message(html: &str) -> Option<String> {
+    static PATTERNS: OnceLock<[Regex; 3]> = OnceLock::new();
+    let [title_pattern, title_message, body_message] = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap(),
+            Regex::new(ERROR_TITLE_PATTERN).unwrap(),
+            Regex::new(ERROR_BODY_PATTERN).unwrap(),
+        ]
+    });
+    if let Some(title) = title_pattern.captures(html).and_then(|c| c.get(1)) {
+        let title = words(title.as_str()).collect::<Vec<_>>().join(" ");
+        if title_message.is_match(&title) {
+            return Some(title);
+        }
+    }
+    let text = text(html);
+    body_message
+        .find(&text)
+        .map(|message| message.as_str().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn error_messages() {
+        assert_eq!(
+            error_message(
+                "<html><head><title>\n  Page Not Found | Publisher\n</title></head></html>"
+            ),
+            Some("Page Not Found Publisher".to_string())
+        );
+        assert_eq!(
+            error_message("<title>Books</title><p>Sorry, the page you were looking for could not be found.</p>"),
+            Some("page you were looking for could not".to_string())
+        );
+        assert_eq!(
+            error_message(
+                "<title>Finding lost manuscripts</title><p>The archive holds 404 letters.</p>"
+            ),
+            None
+        );
+        // scripts are not part of the text
+        assert_eq!(
+            error_message("<title>Books</title><script>show('Page not found')</script>"),
+            None
+        );
+    }
+
+    #[test]
+    fn sibling_paths() {
+        assert_eq!(
+            sibling(&url("https://example.com/books/obp-0001?ref=pdf"), "x1"),
+            url("https://example.com/books/x1")
+        );
+        assert_eq!(
+            sibling(&url("https://example.com/books/obp-0001/"), "x1"),
+            url("https://example.com/books/x1/")
+        );
+    }
+
+    #[test]
+    fn page_similarity() {
+        let ignored = path_words(&url("https://example.com/books/obp-0001"));
+        let page = "<h1>Sorry</h1><p>We moved things around, try searching for obp 0001 in the catalogue.</p>";
+        let missing =
+            "<h1>Sorry</h1><p>We moved things around, try searching for f00d in the catalogue.</p>";
+        let ignored_missing: HashSet<String> =
+            ignored.iter().cloned().chain(["f00d".into()]).collect();
+        assert_eq!(
+            similarity(
+                &shingles(page, &ignored_missing),
+                &shingles(missing, &ignored_missing)
+            ),
+            1.0
+        );
+        let book = "<h1>The Theory of Stuff</h1><p>A book about all kinds of things, in the catalogue.</p>";
+        assert!(similarity(&shingles(book, &ignored), &shingles(missing, &ignored)) < 0.5);
+    }
+
+    #[tokio::test]
+    async fn detect_soft_404() {
+        let mut server = mockito::Server::new_async().await;
+        let client = ClientWithMiddleware::from(
+            reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()
+                .unwrap(),
+        );
+        let root = server.url();
+        let chain = |path: &str| {
+            let mut chain = RedirectChain::new(url(&format!("{}{}", root, path)));
+            chain.set_status(TargetStatus::Answered(StatusCode::OK));
+            chain
+        };
+        let catch_all = "<title>Catalogue</title><p>Browse our books by subject or by series.</p>";
+        server
+            .mock("GET", "/books/obp-0001")
+            .with_body("<title>The Theory of Stuff</title><p>A book about all kinds of things.</p>")
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/books/obp-0002")
+            .with_body(catch_all)
+            .create_async()
+            .await;
+        server
+            .mock(
+                "GET",
+                mockito::Matcher::Regex("^/books/[0-9a-f]{32}$".into()),
+            )
+            .with_body(catch_all)
+            .create_async()
+            .await;
+        server
+            .mock("GET", "/gone")
+            .with_body("<title>Oops! Page not found</title>")
+            .create_async()
+            .await;
+
+        assert_eq!(detect(&client, &chain("/books/obp-0001")).await, None);
+        assert_eq!(
+            detect(&client, &chain("/books/obp-0002")).await,
+            Some(Soft404::SameAsMissingPage)
+        );
+        assert_eq!(
+            detect(&client, &chain("/gone")).await,
+            Some(Soft404::ErrorMessage("Oops Page not found".into()))
+        );
+
+        let mut redirected = chain("/books/obp-0003");
+        redirected.push(url(&format!("{}/", root)));
+        redirected.set_status(TargetStatus::Answered(StatusCode::OK));
+        assert_eq!(
+            detect(&client, &redirected).await,
+            Some(Soft404::RedirectToHomepage)
+        );
+        // the homepage itself is not a soft-404
+        assert_eq!(detect(&client, &chain("/")).await, None);
+
+        // documents are not downloaded
+        let pdf_mock = server
+            .mock("GET", "/books/obp-0004.pdf")
+            .expect(0)
+            .create_async()
+            .await;
+        let mut pdf = chain("/books/obp-0004.pdf");
+        pdf.set_content_type(Some("application/pdf".into()));
+        assert_eq!(detect(&client, &pdf).await, None);
+        pdf_mock.assert_async().await;
+    }
+}
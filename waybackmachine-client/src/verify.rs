@@ -0,0 +1,235 @@
+use crate::page::{self, fetch, shingles, similarity, Page};
+use crate::ratelimit::Endpoint;
+use crate::soft404::error_message;
+use regex::Regex;
+use reqwest::StatusCode;
+use reqwest_middleware::ClientWithMiddleware;
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::OnceLock;
+use url::Url;
+
+/// Similarity above which a capture is considered to hold the live page
+const MIN_SIMILARITY: f64 = 0.5;
+
+/// Bot challenges, in the text or the markup of a page
+const CAPTCHA_PATTERN: &str = r"(?i)captcha|verify (that )?you are (a )?human|are you a robot|i'm not a robot|checking your browser|unusual traffic from your|cf-challenge|challenge-platform";
+/// Cookie consent walls, in the text of a page
+const COOKIE_WALL_PATTERN: &str = r"(?i)\b(accept (all )?cookies|cookie (consent|preferences|settings)|consent to (the use of )?cookies|we use cookies)\b";
+
+/// Outcome of comparing a capture, replayed as it was archived, with the live page
+#[derive(Clone, Debug, PartialEq)]
+pub enum CaptureVerification {
+    /// The capture holds the live page: identical, or with the given similarity
+    /// from 0 to 1 for text
+    Verified { similarity: f64 },
+    /// The replay answers with an error status
+    ReplayStatus(StatusCode),
+    /// The capture has another type of content than the live page
+    MimeTypeMismatch { live: String, replay: String },
+    /// The capture is a CAPTCHA or another bot challenge
+    Captcha,
+    /// The capture is a cookie consent wall
+    CookieWall,
+    /// The capture is an error page, with the message found in it
+    ErrorPage(String),
+    /// The capture differs from the live page, with the given similarity from 0 to 1
+    Different { similarity: f64 },
+    /// The capture could not be compared, for the given reason
+    Unverified(String),
+}
+
+impl CaptureVerification {
+    /// Returns `true` if the capture holds the live page
+    pub fn is_verified(&self) -> bool {
+        matches!(self, CaptureVerification::Verified { .. })
+    }
+}
+
+impl fmt::Display for CaptureVerification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureVerification::Verified { similarity } => {
+                write!(f, "verified, {:.0}% similar", similarity * 100.0)
+            }
+            CaptureVerification::ReplayStatus(status) => write!(f, "replay answered {}", status),
+            CaptureVerification::MimeTypeMismatch { live, replay } => {
+                write!(f, "captured {} instead of {}", replay, live)
+            }
+            CaptureVerification::Captcha => write!(f, "captured a CAPTCHA"),
+            CaptureVerification::CookieWall => write!(f, "captured a cookie wall"),
+            CaptureVerification::ErrorPage(message) => {
+                write!(f, "captured an error page \"{}\"", message)
+            }
+            CaptureVerification::Different { similarity } => {
+                write!(
+                    f,
+                    "differs from the live page, {:.0}% similar",
+                    similarity * 100.0
+                )
+            }
+            CaptureVerification::Unverified(reason) => write!(f, "not verified, {}", reason),
+        }
+    }
+}
+
+/// Turns a replay URL of the form `{prefix}{timestamp}{modifier}/{url}`, used by the
+/// Wayback Machine and pywb, into the `id_` replay of the content as it was captured,
+/// without rewritten links or inserted banner
+pub fn raw_replay_url(snapshot_url: &str) -> Option<String> {
+    static REPLAY: OnceLock<Regex> = OnceLock::new();
+    let replay = REPLAY
+        .get_or_init(|| Regex::new(r"^(https?://.+?/)(\d{4,14})(?:[a-z]{2}_)?/(.+)$").unwrap());
+    let captures = replay.captures(snapshot_url)?;
+    Some(format!(
+        "{}{}id_/{}",
+        &captures[1], &captures[2], &captures[3]
+    ))
+}
+
+/// Fetches the raw replay of a capture and compares it with the live page
+pub(crate) async fn verify(
+    http_client: &ClientWithMiddleware,
+    raw_replay_url: &str,
+    live: &Page,
+) -> CaptureVerification {
+    let replay_url = match Url::parse(raw_replay_url) {
+        Ok(replay_url) => replay_url,
+        Err(e) => return CaptureVerification::Unverified(format!("invalid replay URL: {}", e)),
+    };
+    match fetch(http_client, &replay_url, Endpoint::Check).await {
+        Ok(replay) => compare(live, &replay),
+        Err(e) => CaptureVerification::Unverified(format!("replay failed: {}", e)),
+    }
+}
+
+fn compare(live: &Page, replay: &Page) -> CaptureVerification {
+    if !replay.status.is_success() {
+        return CaptureVerification::ReplayStatus(replay.status);
+    }
+    if let (Some(live), Some(replay)) = (live.mime_type(), replay.mime_type()) {
+        if live != replay {
+            return CaptureVerification::MimeTypeMismatch { live, replay };
+        }
+    }
+    if live.body == replay.body {
+        return CaptureVerification::Verified { similarity: 1.0 };
+    }
+    // other content, such as PDFs or images, can only be compared byte for byte
+    let similarity = if live.is_text() && replay.is_text() {
+        similarity(
+            &shingles(&live.html(), &HashSet::new()),
+            &shingles(&replay.html(), &HashSet::new()),
+        )
+    } else {
+        0.0
+    };
+    if similarity >= MIN_SIMILARITY {
+        return CaptureVerification::Verified { similarity };
+    }
+
+    static PATTERNS: OnceLock<[Regex; 2]> = OnceLock::new();
+    let [captcha, cookie_wall] = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(CAPTCHA_PATTERN).unwrap(),
+            Regex::new(COOKIE_WALL_PATTERN).unwrap(),
+        ]
+    });
+    let html = replay.html();
+    let text = page::text(&html);
+    if captcha.is_match(&html) {
+        CaptureVerification::Captcha
+    } else if cookie_wall.is_match(&text) {
+        CaptureVerification::CookieWall
+    } else if let Some(message) = error_message(&html) {
+        CaptureVerification::ErrorPage(message)
+    } else {
+        CaptureVerification::Different { similarity }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(status: StatusCode, content_type: &str, body: &str) -> Page {
+        Page {
+            status,
+            location: None,
+            content_type: Some(content_type.to_string()),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn raw_replay_urls() {
+        assert_eq!(
+            raw_replay_url("https://web.archive.org/web/20240501100000/https://example.com/a?b=c")
+                .as_deref(),
+            Some("https://web.archive.org/web/20240501100000id_/https://example.com/a?b=c")
+        );
+        // replay modifiers are replaced
+        assert_eq!(
+            raw_replay_url("http://localhost:8080/local/20240501100000im_/https://example.com/")
+                .as_deref(),
+            Some("http://localhost:8080/local/20240501100000id_/https://example.com/")
+        );
+        assert_eq!(raw_replay_url("https://perma.cc/ABCD-1234"), None);
+    }
+
+    #[test]
+    fn compare_with_live_page() {
+        let live = page(
+            StatusCode::OK,
+            "text/html; charset=utf-8",
+            "<title>The Theory of Stuff</title><p>A book about all kinds of things, \
+             published in 2019 and available in print and online.</p>",
+        );
+        let html = |body: &str| page(StatusCode::OK, "text/html", body);
+        assert_eq!(
+            compare(&live, &html(&String::from_utf8_lossy(&live.body))),
+            CaptureVerification::Verified { similarity: 1.0 }
+        );
+        assert!(compare(
+            &live,
+            &html(
+                "<title>The Theory of Stuff</title><nav>Home</nav><p>A book about all kinds of \
+                 things, published in 2019 and available in print and online.</p>"
+            )
+        )
+        .is_verified());
+        assert_eq!(
+            compare(&live, &page(StatusCode::NOT_FOUND, "text/html", "")),
+            CaptureVerification::ReplayStatus(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            compare(&live, &page(StatusCode::OK, "application/json", "{}")),
+            CaptureVerification::MimeTypeMismatch {
+                live: "text/html".into(),
+                replay: "application/json".into()
+            }
+        );
+        assert_eq!(
+            compare(
+                &live,
+                &html("<title>Just a moment...</title><div id=\"cf-challenge-running\"></div>")
+            ),
+            CaptureVerification::Captcha
+        );
+        assert_eq!(
+            compare(
+                &live,
+                &html("<h1>Before you continue</h1><p>We use cookies to improve our site.</p><button>Accept all cookies</button>")
+            ),
+            CaptureVerification::CookieWall
+        );
+        assert_eq!(
+            compare(&live, &html("<title>404 Not Found</title>")),
+            CaptureVerification::ErrorPage("404 Not Found".into())
+        );
+        assert!(matches!(
+            compare(&live, &html("<p>Something else entirely.</p>")),
+            CaptureVerification::Different { .. }
+        ));
+    }
+}
@@ -0,0 +1,536 @@
+use crate::backend::{ArchiveBackend, CaptureStatus};
+use crate::ratelimit::Endpoint;
+use crate::snapshot::WAYBACK_TIMESTAMP_FORMAT;
+use crate::verify;
+use crate::{ArchivableUrl, Error, Snapshot};
+use async_trait::async_trait;
+use chrono::{NaiveDateTime, SecondsFormat, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http::Extensions;
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, CONTENT_TYPE, HOST, TRANSFER_ENCODING};
+use reqwest_middleware::ClientWithMiddleware;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use url::Url;
+use uuid::Uuid;
+
+/// Local web archive: URLs are fetched by the client itself and written to a
+/// WARC 1.1 file, with a CDXJ index for lookups and replay (e.g. with pywb).
+///
+/// Records are gzipped one by one, and the file is appended to across runs.
+/// Nothing is sent to a third-party service, which suits sensitive or paywalled material.
+pub struct LocalWarc {
+    directory: PathBuf,
+    prefix: String,
+    replay_endpoint: Option<String>,
+    // serialises writes to the WARC file and its index
+    lock: Mutex<()>,
+}
+
+/// Fields of a CDXJ index line, following pywb
+#[derive(Debug, Deserialize, Serialize)]
+struct IndexEntry {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mime: Option<String>,
+    status: String,
+    digest: String,
+    length: String,
+    offset: String,
+    filename: String,
+}
+
+/// WARC record, before compression
+struct Record {
+    id: String,
+    headers: Vec<(&'static str, String)>,
+    content_type: &'static str,
+    block: Vec<u8>,
+}
+
+impl LocalWarc {
+    /// Constructs a local archive writing `archive.warc.gz` and `archive.cdxj`
+    /// in the given directory
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        LocalWarc {
+            directory: directory.into(),
+            prefix: "archive".into(),
+            replay_endpoint: None,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Sets the name of the WARC file and its index, without extension
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Sets the replay service snapshots are reported at, as
+    /// `{replay_endpoint}{timestamp}/{url}`, instead of the `file:` URL of the WARC file
+    pub fn replay_endpoint(mut self, replay_endpoint: impl Into<String>) -> Self {
+        self.replay_endpoint = Some(replay_endpoint.into());
+        self
+    }
+
+    fn warc_filename(&self) -> String {
+        format!("{}.warc.gz", self.prefix)
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.directory.join(format!("{}.cdxj", self.prefix))
+    }
+
+    fn replay_url(&self, url: &str, timestamp: &str, offset: &str) -> String {
+        match &self.replay_endpoint {
+            Some(replay_endpoint) => format!("{}{}/{}", replay_endpoint, timestamp, url),
+            None => {
+                let path = self.directory.join(self.warc_filename());
+                let path = fs::canonicalize(&path).unwrap_or(path);
+                match Url::from_file_path(&path) {
+                    Ok(mut file_url) => {
+                        file_url.set_fragment(Some(&format!("offset={}", offset)));
+                        file_url.to_string()
+                    }
+                    Err(_) => format!("{}#offset={}", path.display(), offset),
+                }
+            }
+        }
+    }
+
+    /// Appends the records to the WARC file, preceded by a `warcinfo` record if
+    /// the file is new, and returns the offset and compressed length of the last one
+    fn append(&self, records: &[Record]) -> Result<(u64, u64), Error> {
+        let path = self.directory.join(self.warc_filename());
+        fs::create_dir_all(&self.directory).map_err(|e| Error::storage(&self.directory, e))?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| Error::storage(&path, e))?;
+        let mut offset = file.metadata().map_err(|e| Error::storage(&path, e))?.len();
+        let mut written = (offset, 0);
+        let warcinfo = (offset == 0).then(|| warcinfo_record(&self.warc_filename()));
+        for record in warcinfo.iter().chain(records) {
+            let compressed = record.compress().map_err(|e| Error::storage(&path, e))?;
+            file.write_all(&compressed)
+                .map_err(|e| Error::storage(&path, e))?;
+            written = (offset, compressed.len() as u64);
+            offset += compressed.len() as u64;
+        }
+        file.sync_data().map_err(|e| Error::storage(&path, e))?;
+        Ok(written)
+    }
+
+    /// Appends the line to the index, left unsorted until `sort_index` is called
+    fn index(&self, line: String) -> Result<(), Error> {
+        let path = self.index_path();
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes()))
+            .map_err(|e| Error::storage(&path, e))
+    }
+
+    /// Sorts the CDXJ index, as replay tools such as pywb expect. Captures only
+    /// append to the index, so that it is not rewritten for each of them: this is
+    /// to be called once they are done.
+    pub fn sort_index(&self) -> Result<(), Error> {
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let path = self.index_path();
+        let mut lines = self.index_lines()?;
+        if lines.is_sorted() {
+            return Ok(());
+        }
+        lines.sort();
+        let temporary = path.with_extension("cdxj.tmp");
+        fs::write(&temporary, lines.join("\n") + "\n")
+            .and_then(|_| fs::rename(&temporary, &path))
+            .map_err(|e| Error::storage(&path, e))
+    }
+
+    fn index_lines(&self) -> Result<Vec<String>, Error> {
+        let path = self.index_path();
+        match File::open(&path) {
+            Ok(file) => BufReader::new(file)
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.is_empty()))
+                .collect::<Result<_, _>>()
+                .map_err(|e| Error::storage(&path, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(Error::storage(&path, e)),
+        }
+    }
+}
+
+impl Record {
+    fn new(warc_type: &str, content_type: &'static str, block: Vec<u8>) -> Self {
+        Record {
+            id: format!("<urn:uuid:{}>", Uuid::new_v4()),
+            headers: vec![("WARC-Type", warc_type.to_string())],
+            content_type,
+            block,
+        }
+    }
+
+    fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    fn compress(&self) -> io::Result<Vec<u8>> {
+        let mut header = String::from("WARC/1.1\r\n");
+        header.push_str(&format!("WARC-Record-ID: {}\r\n", self.id));
+        for (name, value) in &self.headers {
+            header.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        header.push_str(&format!("Content-Type: {}\r\n", self.content_type));
+        header.push_str(&format!("Content-Length: {}\r\n\r\n", self.block.len()));
+
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(header.as_bytes())?;
+        encoder.write_all(&self.block)?;
+        encoder.write_all(b"\r\n\r\n")?;
+        encoder.finish()
+    }
+}
+
+fn warcinfo_record(filename: &str) -> Record {
+    let fields = format!(
+        "software: waybackmachine-client/{}\r\nformat: WARC File Format 1.1\r\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    Record::new("warcinfo", "application/warc-fields", fields.into_bytes())
+        .header("WARC-Date", warc_date())
+        .header("WARC-Filename", filename)
+}
+
+fn warc_date() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Base32 SHA-1 digest, as used in WARC and CDX files
+fn sha1_digest(data: &[u8]) -> String {
+    let digest = Sha1::digest(data);
+    format!(
+        "sha1:{}",
+        base32::encode(base32::Alphabet::Rfc4648 { padding: true }, &digest)
+    )
+}
+
+fn write_headers(block: &mut Vec<u8>, headers: &HeaderMap) {
+    for (name, value) in headers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+    block.extend_from_slice(b"\r\n");
+}
+
+/// Sort-friendly URL form used as the index key, e.g. `com,example)/page?q=1`
+pub(crate) fn surt(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host);
+    let mut key: Vec<&str> = host.split('.').collect();
+    key.reverse();
+    let mut surt = key.join(",");
+    if let Some(port) = url.port() {
+        surt.push_str(&format!(":{}", port));
+    }
+    surt.push(')');
+    surt.push_str(&url.path().to_ascii_lowercase());
+    if let Some(query) = url.query() {
+        let mut params: Vec<&str> = query.split('&').collect();
+        params.sort();
+        surt.push('?');
+        surt.push_str(&params.join("&").to_ascii_lowercase());
+    }
+    surt
+}
+
+#[async_trait]
+impl ArchiveBackend for LocalWarc {
+    fn name(&self) -> &str {
+        "local WARC"
+    }
+
+    /// Captures are replayed raw by pywb, if they are served by a replay endpoint
+    fn raw_replay_url(&self, snapshot_url: &str) -> Option<String> {
+        self.replay_endpoint.as_ref()?;
+        verify::raw_replay_url(snapshot_url)
+    }
+
+    /// Looks up the most recent capture of the URL in the CDXJ index, sorted or not
+    async fn latest_snapshot(
+        &self,
+        _http_client: &ClientWithMiddleware,
+        to_check: &ArchivableUrl,
+    ) -> Result<Option<Snapshot>, Error> {
+        let key = format!("{} ", surt(&to_check.without_fragment()));
+        let latest = self
+            .index_lines()?
+            .into_iter()
+            .filter(|line| line.starts_with(&key))
+            .max();
+        let Some(line) = latest else {
+            return Ok(None);
+        };
+        let mut fields = line[key.len()..].splitn(2, ' ');
+        let timestamp = fields.next().unwrap_or_default();
+        let entry: IndexEntry = serde_json::from_str(fields.next().unwrap_or_default())
+            .map_err(|e| Error::cannot_check("invalid CDXJ index", e))?;
+        Ok(Some(Snapshot {
+            url: self.replay_url(&entry.url, timestamp, &entry.offset),
+            timestamp: NaiveDateTime::parse_from_str(timestamp, WAYBACK_TIMESTAMP_FORMAT)?,
+        }))
+    }
+
+    /// URLs are fetched by the client itself, without following redirects
+    fn fetches_targets(&self) -> bool {
+        true
+    }
+
+    /// Fetches the URL and writes its request and response records, a redirect
+    /// being stored as such. Captures are complete once written, so they are
+    /// never pending.
+    async fn request_capture(
+        &self,
+        http_client: &ClientWithMiddleware,
+        to_archive: &ArchivableUrl,
+    ) -> Result<CaptureStatus, Error> {
+        let request = http_client
+            .get(to_archive.without_fragment())
+            .build()
+            .map_err(|e| Error::request(Endpoint::Target, e))?;
+        let mut request_block = format!(
+            "GET {} HTTP/1.1\r\n",
+            &request.url()[url::Position::BeforePath..url::Position::AfterQuery]
+        )
+        .into_bytes();
+        let mut request_headers = request.headers().clone();
+        if let Ok(host) = request.url()[url::Position::BeforeHost..url::Position::AfterPort].parse()
+        {
+            request_headers.insert(HOST, host);
+        }
+        write_headers(&mut request_block, &request_headers);
+
+        let mut extensions = Extensions::new();
+        extensions.insert(Endpoint::Target);
+        let response = http_client
+            .execute_with_extensions(request, &mut extensions)
+            .await
+            .map_err(|e| Error::request(Endpoint::Target, e))?;
+        // the capture is recorded under the URL requested, whatever it answered
+        let target = to_archive.without_fragment();
+        let target_uri = target.to_string();
+        let status = response.status();
+        let remote_addr = response.remote_addr();
+        let mut headers = response.headers().clone();
+        let mut response_block = format!(
+            "{:?} {} {}\r\n",
+            response.version(),
+            status.as_str(),
+            status.canonical_reason().unwrap_or_default()
+        )
+        .into_bytes();
+        let payload = response
+            .bytes()
+            .await
+            .map_err(|e| Error::request(Endpoint::Target, e))?;
+        // the body is stored decoded, so its framing must match
+        headers.remove(TRANSFER_ENCODING);
+        headers.insert(CONTENT_LENGTH, payload.len().into());
+        write_headers(&mut response_block, &headers);
+        response_block.extend_from_slice(&payload);
+
+        let date = Utc::now();
+        let warc_date = date.to_rfc3339_opts(SecondsFormat::Secs, true);
+        let payload_digest = sha1_digest(&payload);
+        let block_digest = sha1_digest(&response_block);
+        let mut response_record = Record::new(
+            "response",
+            "application/http;msgtype=response",
+            response_block,
+        )
+        .header("WARC-Date", warc_date.clone())
+        .header("WARC-Target-URI", target_uri.clone())
+        .header("WARC-Block-Digest", block_digest)
+        .header("WARC-Payload-Digest", payload_digest.clone());
+        if let Some(remote_addr) = remote_addr {
+            response_record =
+                response_record.header("WARC-IP-Address", remote_addr.ip().to_string());
+        }
+        let request_record =
+            Record::new("request", "application/http;msgtype=request", request_block)
+                .header("WARC-Date", warc_date)
+                .header("WARC-Target-URI", target_uri.clone())
+                .header("WARC-Concurrent-To", response_record.id.clone());
+
+        let timestamp = date.format(WAYBACK_TIMESTAMP_FORMAT).to_string();
+        let mime = headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|mime| mime.trim().to_string());
+
+        let _lock = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        // the response comes last, so that the returned offset is the one to replay
+        let (offset, length) = self.append(&[request_record, response_record])?;
+        let entry = IndexEntry {
+            url: target_uri.clone(),
+            mime,
+            status: status.as_u16().to_string(),
+            digest: payload_digest,
+            length: length.to_string(),
+            offset: offset.to_string(),
+            filename: self.warc_filename(),
+        };
+        let entry = serde_json::to_string(&entry)
+            .map_err(|e| Error::storage(self.index_path(), e.into()))?;
+        self.index(format!("{} {} {}", surt(&target), timestamp, entry))?;
+        Ok(CaptureStatus::Done(self.replay_url(
+            &target_uri,
+            &timestamp,
+            &offset.to_string(),
+        )))
+    }
+
+    async fn capture_status(
+        &self,
+        _http_client: &ClientWithMiddleware,
+        _to_archive: &ArchivableUrl,
+        job_id: &str,
+    ) -> Result<CaptureStatus, Error> {
+        Ok(CaptureStatus::Done(job_id.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SchemePolicy;
+    use flate2::read::MultiGzDecoder;
+    use std::io::Read;
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("{}-{}", name, Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn surt_keys() {
+        let surt_of = |url: &str| surt(&Url::parse(url).unwrap());
+        assert_eq!(surt_of("https://www.Example.com/Page"), "com,example)/page");
+        assert_eq!(
+            surt_of("http://sub.example.com:8080/a?b=2&a=1"),
+            "com,example,sub:8080)/a?a=1&b=2"
+        );
+    }
+
+    #[tokio::test]
+    async fn index_sorted_once_done() {
+        let directory = temporary_directory("warc-index");
+        let warc = LocalWarc::new(&directory);
+        for line in [
+            "org,example)/b 20240101000000 {}",
+            "com,example)/a 20240101000000 {}",
+        ] {
+            warc.index(line.into()).unwrap();
+        }
+        // captures only append
+        assert_eq!(
+            warc.index_lines().unwrap(),
+            [
+                "org,example)/b 20240101000000 {}",
+                "com,example)/a 20240101000000 {}"
+            ]
+        );
+        warc.sort_index().unwrap();
+        assert_eq!(
+            fs::read_to_string(directory.join("archive.cdxj")).unwrap(),
+            "com,example)/a 20240101000000 {}\norg,example)/b 20240101000000 {}\n"
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn capture_and_lookup() {
+        let mut server = mockito::Server::new_async().await;
+        let directory = temporary_directory("warc-capture");
+        let warc = LocalWarc::new(&directory).replay_endpoint("http://localhost:8080/local/");
+        let http_client = ClientWithMiddleware::from(reqwest::Client::new());
+        let to_archive = ArchivableUrl::parse_with_policy(
+            &format!("{}/paper#top", server.url()),
+            &SchemePolicy::default(),
+        )
+        .unwrap();
+
+        assert!(warc
+            .latest_snapshot(&http_client, &to_archive)
+            .await
+            .unwrap()
+            .is_none());
+        server
+            .mock("GET", "/paper")
+            .with_status(200)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body("<p>Preserved</p>")
+            .create_async()
+            .await;
+        let CaptureStatus::Done(replay_url) = warc
+            .request_capture(&http_client, &to_archive)
+            .await
+            .unwrap()
+        else {
+            panic!("local captures are never pending");
+        };
+        assert!(replay_url.starts_with("http://localhost:8080/local/"));
+        assert!(replay_url.ends_with(&format!("/{}/paper", server.url())));
+
+        let snapshot = warc
+            .latest_snapshot(&http_client, &to_archive)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(snapshot.url, replay_url);
+
+        let mut contents = String::new();
+        MultiGzDecoder::new(File::open(directory.join("archive.warc.gz")).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert!(contents.starts_with("WARC/1.1\r\n"));
+        assert_eq!(contents.matches("WARC/1.1\r\n").count(), 3);
+        assert!(contents.contains("WARC-Type: warcinfo\r\n"));
+        assert!(contents.contains("GET /paper HTTP/1.1\r\n"));
+        assert!(contents.contains("HTTP/1.1 200 OK\r\n"));
+        assert!(contents.contains("<p>Preserved</p>\r\n\r\n"));
+
+        let index = fs::read_to_string(directory.join("archive.cdxj")).unwrap();
+        let (key, rest) = index.trim().split_once(' ').unwrap();
+        assert_eq!(key, surt(&to_archive.without_fragment()));
+        let entry: IndexEntry = serde_json::from_str(rest.split_once(' ').unwrap().1).unwrap();
+        assert_eq!(entry.status, "200");
+        assert_eq!(entry.mime.as_deref(), Some("text/html"));
+
+        // the indexed offset and length delimit the gzipped response record
+        let warc_file = fs::read(directory.join("archive.warc.gz")).unwrap();
+        let offset: usize = entry.offset.parse().unwrap();
+        let length: usize = entry.length.parse().unwrap();
+        let mut record = String::new();
+        MultiGzDecoder::new(&warc_file[offset..offset + length])
+            .read_to_string(&mut record)
+            .unwrap();
+        assert!(record.contains("WARC-Type: response\r\n"));
+        assert_eq!(record.matches("WARC/1.1\r\n").count(), 1);
+
+        fs::remove_dir_all(directory).unwrap();
+    }
+}